@@ -0,0 +1,70 @@
+//! Bot that always plays the first empty cell, using the bot library.
+//!
+//! ```bash
+//! cargo run --example first_free --features native -- [username password] [--host <host>] [--port <port>] [--plain | --insecure]
+//! ```
+
+use clap::Parser;
+
+use common::Credentials;
+use common::bot::{self, Config};
+use common::tic_tac_toe::ClientState;
+
+#[derive(Parser)]
+#[command(about = "Bot that always plays the first empty cell")]
+struct Args {
+    /// Account to play as, a guest if not given
+    #[arg(requires = "password")]
+    username: Option<String>,
+
+    password: Option<String>,
+
+    /// Server to connect to
+    #[arg(long, env = "BOARD_GAMES_HOST", default_value = common::REMOTE_IP)]
+    host: String,
+
+    /// Port the server is listening on
+    #[arg(long, env = "BOARD_GAMES_PORT", default_value_t = common::REMOTE_PORT)]
+    port: u16,
+
+    /// Connect with plain websockets (`ws://`) instead of TLS,
+    /// for servers run with `--plain`
+    #[arg(long, env = "BOARD_GAMES_PLAIN")]
+    plain: bool,
+
+    /// Don't verify the server's certificate. Only use this for testing
+    #[arg(long, env = "BOARD_GAMES_INSECURE", conflicts_with = "plain")]
+    insecure: bool,
+}
+
+fn main() {
+    // shows the errors the server sends, e.g. for an invalid request
+    env_logger::Builder::new()
+        .filter_level(log::LevelFilter::Warn)
+        .parse_default_env()
+        .init();
+    let args = Args::parse();
+    let credentials = match (args.username, args.password) {
+        (Some(username), Some(password)) => Some(Credentials { username, password }),
+        _ => None,
+    };
+
+    let config = Config {
+        host: args.host,
+        port: args.port,
+        credentials,
+        games: Some(1),
+        plain: args.plain,
+        insecure: args.insecure,
+        ..Config::default()
+    };
+    let first_free = |state: &ClientState| {
+        let (x, y) = state.board.empty_cells()[0];
+        (state.piece.clone(), x, y)
+    };
+
+    match bot::play(&config, first_free) {
+        Ok(results) => println!("Finished: {results:?}"),
+        Err(e) => eprintln!("{e}"),
+    }
+}
@@ -0,0 +1,98 @@
+//! Measures how much CPU the server uses while lots of clients are
+//! connected but idle. Starts a server built alongside it with plain
+//! websockets and a throwaway database, so build both first. Linux only,
+//! as the CPU time is read from `/proc`.
+//!
+//! ```bash
+//! cargo build --release --features native --bin server
+//! cargo run --release --example idle_clients --features native -- [clients] [seconds]
+//! ```
+
+use std::{
+    env, fs, thread,
+    net::TcpListener,
+    path::PathBuf,
+    process::{Child, Command, Stdio},
+    time::{Duration, Instant},
+};
+
+use common::bot;
+
+/// Clock ticks per second in `/proc/<pid>/stat`, `USER_HZ` is 100 on Linux.
+const TICKS_PER_SEC: f64 = 100.0;
+
+fn main() {
+    let mut args = env::args().skip(1);
+    let clients: usize = args.next().map_or(500, |n| n.parse().expect("clients should be a number"));
+    let secs: u64 = args.next().map_or(10, |n| n.parse().expect("seconds should be a number"));
+
+    let (mut server, port, database) = start_server();
+    let pid = server.id();
+
+    let connected = Instant::now();
+    for i in 0..clients {
+        let mut socket = match bot::connect("127.0.0.1", port, None) {
+            Ok(socket) => socket,
+            Err(e) => {
+                eprintln!("Only connected {i} clients. {e}");
+                break;
+            },
+        };
+        // keep reading so pings are answered, otherwise do nothing
+        thread::spawn(move|| while socket.read_message().is_ok() {});
+    }
+    println!("Connected {clients} clients in {:.1}s", connected.elapsed().as_secs_f64());
+
+    // let the handshakes and lobby settle before measuring
+    thread::sleep(Duration::from_secs(2));
+    let before = cpu_time(pid);
+    let started = Instant::now();
+    thread::sleep(Duration::from_secs(secs));
+    let used = cpu_time(pid) - before;
+    let elapsed = started.elapsed().as_secs_f64();
+
+    println!("Server CPU over {elapsed:.1}s: {used:.2}s, {:.1}% of one core", used / elapsed * 100.0);
+    let _ = server.kill();
+    let _ = server.wait();
+    let _ = fs::remove_file(database);
+}
+
+/// Starts the server next to this example's binary on a free
+/// port, returning it along with the port and its database.
+fn start_server() -> (Child, u16, PathBuf) {
+    let exe = env::current_exe().expect("Unable to find the example's path");
+    // examples are built in `target/<profile>/examples`
+    let binary = exe.parent().and_then(|dir| dir.parent()).map(|dir| dir.join("server")).unwrap_or_default();
+    if !binary.exists() {
+        panic!("No server at {}, build it with `cargo build --features native --bin server`", binary.display());
+    }
+
+    let port = TcpListener::bind("127.0.0.1:0").and_then(|l| l.local_addr()).map(|addr| addr.port()).expect("No free port");
+    let database: PathBuf = env::temp_dir().join(format!("idle-clients-{port}.db"));
+    let child = Command::new(binary)
+        .args([&port.to_string(), "--plain", "--address", "127.0.0.1", "--log", "warn", "--database"])
+        .arg(&database)
+        .stdout(Stdio::null())
+        .spawn()
+        .expect("Unable to start the server");
+
+    // wait for it to listen
+    let deadline = Instant::now() + Duration::from_secs(10);
+    while bot::connect("127.0.0.1", port, None).is_err() {
+        if Instant::now() > deadline {
+            panic!("The server didn't start listening");
+        }
+        thread::sleep(Duration::from_millis(100));
+    }
+    (child, port, database)
+}
+
+/// User and system CPU time used by the process so far, in seconds.
+fn cpu_time(pid: u32) -> f64 {
+    let stat = fs::read_to_string(format!("/proc/{pid}/stat")).expect("Unable to read the server's CPU time");
+    // the name in brackets may contain spaces, so split after it
+    let fields: Vec<&str> = stat.rsplit_once(')').map(|(_, rest)| rest).unwrap_or_default().split_whitespace().collect();
+    // utime and stime are the 14th and 15th fields, the 12th and 13th after the name
+    let ticks: u64 = fields[11].parse::<u64>().unwrap_or(0) + fields[12].parse::<u64>().unwrap_or(0);
+    ticks as f64 / TICKS_PER_SEC
+}
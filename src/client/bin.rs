@@ -1,9 +1,21 @@
 use std::{
     io::{self, prelude::*, stdout},
     thread,
-    sync::mpsc::{channel, Receiver},
+    sync::mpsc::channel,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    time::{SystemTime, UNIX_EPOCH},
 };
+use clap::Parser;
 
+mod engine;
+use engine::Engine;
+
+use common::{AccountRequest, ChatChannel, ChatMessage, ClientMessage, Credentials, Game, ServerMessage};
+use common::ai::{Ai, Difficulty};
+use common::bot;
+use common::history::{self, GameRecord, GameSummary};
 use common::tic_tac_toe::{
     self,
     Message,
@@ -11,35 +23,210 @@ use common::tic_tac_toe::{
     Piece,
     Board,
     End,
+    Offer,
+    Turn,
 };
 
+#[derive(Parser)]
+#[command(about = "Command line client for the board game server")]
+struct Args {
+    /// Server to connect to
+    #[arg(long, env = "BOARD_GAMES_HOST", default_value = common::REMOTE_IP)]
+    host: String,
+
+    /// Port the server is listening on
+    #[arg(long, env = "BOARD_GAMES_PORT", default_value_t = common::REMOTE_PORT)]
+    port: u16,
+
+    /// Game to play and show the leaderboard for
+    #[arg(long, env = "BOARD_GAMES_GAME", default_value = "tictactoe")]
+    game: Game,
+
+    /// Log into a registered account instead of playing as a guest.
+    /// The password is read from stdin.
+    #[arg(long)]
+    user: Option<String>,
+
+    /// Create the account given by `--user` before logging in
+    #[arg(long, requires = "user")]
+    sign_up: bool,
+
+    /// Keep the session for `--user` in this file after logging in,
+    /// and resume it next time instead of asking for the password
+    #[arg(long, value_name = "FILE", env = "BOARD_GAMES_SESSION", requires = "user")]
+    session: Option<PathBuf>,
+
+    /// Show the highest rated players after connecting
+    #[arg(long)]
+    leaderboard: bool,
+
+    /// List recently finished games, only your own if logged in
+    #[arg(long)]
+    history: bool,
+
+    /// Show every move of a finished game, using its id from `--history`
+    #[arg(long, value_name = "ID")]
+    record: Option<i64>,
+
+    /// Save the game from `--record` to a PGN like text file
+    #[arg(long, value_name = "FILE", requires = "record")]
+    export: Option<PathBuf>,
+
+    /// Step through a game saved with `--export`, without connecting to the server
+    #[arg(long, value_name = "FILE", conflicts_with_all = ["record", "history", "leaderboard", "games", "watch"])]
+    replay: Option<PathBuf>,
+
+    /// List the games being played right now
+    #[arg(long)]
+    games: bool,
+
+    /// Spectate a live game, using its id from `--games`,
+    /// instead of looking for a game to play
+    #[arg(long, value_name = "ID", conflicts_with = "bot")]
+    watch: Option<u64>,
+
+    /// Play the computer instead of waiting for another player,
+    /// one of random, greedy or minimax
+    #[arg(long, value_name = "DIFFICULTY")]
+    bot: Option<Difficulty>,
+
+    /// Play at this terminal without connecting to the server, taking
+    /// turns with another person or against the computer with `--bot`
+    #[arg(long, conflicts_with_all = ["user", "record", "history", "leaderboard", "games", "watch", "replay"])]
+    local: bool,
+
+    /// Let a program play instead of typing moves, e.g. `--engine "python3 bot.py"`.
+    /// The account given by `--user` is flagged as a bot. See `engine.rs`
+    /// for the line based protocol it needs to speak over stdin and stdout
+    #[arg(long, value_name = "COMMAND", requires = "user", conflicts_with_all = ["watch", "local", "replay"])]
+    engine: Option<String>,
+
+    /// Connect with plain websockets (`ws://`) instead of TLS,
+    /// for servers run with `--plain`
+    #[arg(long, env = "BOARD_GAMES_PLAIN")]
+    plain: bool,
+
+    /// Trust the certificate authority in this PEM file, e.g. the
+    /// one used to sign a local server's self signed certificate
+    #[arg(long, value_name = "FILE", env = "BOARD_GAMES_CA", conflicts_with = "plain")]
+    ca: Option<PathBuf>,
+
+    /// Don't verify the server's certificate. Only use this for testing
+    #[arg(long, env = "BOARD_GAMES_INSECURE", conflicts_with = "plain")]
+    insecure: bool,
+}
+
 fn main() {
-    let ip = format!("wss://{}:{}", common::REMOTE_IP, common::REMOTE_PORT);
-    let ip_str = ip.to_string();
+    let args = Args::parse();
+    if let Some(path) = &args.replay {
+        replay(path);
+        return;
+    }
+    if args.local {
+        local(args.bot);
+        return;
+    }
+    let saved = match (&args.user, &args.session) {
+        (Some(username), Some(path)) if !args.sign_up => load_session(path, username),
+        _ => None,
+    };
+    let mut resuming = saved.is_some();
+    let login = args.user.clone().map(|username| {
+        if let Some(token) = saved {
+            println!("Resuming the session for {username}");
+            return ClientMessage::Account(AccountRequest::Resume(token));
+        }
+        print!("Password for {username}: ");
+        stdout().flush().unwrap();
+        let mut password = String::new();
+        io::stdin().read_line(&mut password).unwrap();
+        let credentials = Credentials { username, password: password.trim_end().to_string() };
+
+        if args.sign_up {
+            ClientMessage::Account(AccountRequest::SignUp(credentials))
+        } else {
+            ClientMessage::Account(AccountRequest::Login(credentials))
+        }
+    });
+
+    let tls = if args.plain {
+        None
+    } else {
+        match bot::tls(args.ca.as_deref(), args.insecure) {
+            Ok(tls) => Some(tls),
+            Err(e) => {
+                println!("{e}");
+                return;
+            },
+        }
+    };
+    let scheme = if args.plain { "ws" } else { "wss" };
+
+    match bot::connect(&args.host, args.port, tls) {
+        Ok(mut socket) => {
+            println!("Successfully connected to {scheme}://{}:{}.", args.host, args.port);
+            println!("Chat with /say <message>, or /lobby <message> to talk to everyone.");
 
-    match tungstenite::connect(ip) {
-        Ok((mut socket, _)) => {
-            println!("Successfully connected to {ip_str}.");
+            // join the queue straight away as a guest, otherwise wait
+            // until the login has been handled. spectators never join
+            let mut queued = login.is_none() || args.watch.is_some();
+            let find_game = match args.bot {
+                Some(difficulty) => ClientMessage::PlayBot(args.game, difficulty),
+                None => ClientMessage::FindGame(args.game),
+            };
+            match login {
+                Some(login) => {
+                    send(&mut socket, login);
+                    if args.engine.is_some() {
+                        send(&mut socket, ClientMessage::Account(AccountRequest::FlagBot));
+                    }
+                },
+                None if args.watch.is_none() => send(&mut socket, find_game.clone()),
+                None => (),
+            }
+            let mut requests = Vec::new();
+            if args.leaderboard { requests.push(ClientMessage::Leaderboard(args.game)); }
+            if args.history { requests.push(ClientMessage::History(args.user.clone())); }
+            if let Some(id) = args.record { requests.push(ClientMessage::GameRecord(id)); }
+            if args.games { requests.push(ClientMessage::LiveGames); }
+            if let Some(id) = args.watch { requests.push(ClientMessage::Spectate(id)); }
+            for request in requests {
+                send(&mut socket, request);
+            }
 
             // create two threads, one to block on io reading from stdin
             // the other to handle the TcpStream and sending/receiving
             // to the server
 
-            // thread to read input from stdin
-            // and forward to the second thread
+            // thread to read input from stdin, or the
+            // engine if there is one, and forward to the
+            // second thread
             let (tx, rx) = channel::<String>();
-            thread::spawn(move|| {
-                loop {
-                    let mut send = String::new();
-                    io::stdin().read_line(&mut send).unwrap();
-                    tx.send(send).unwrap();
-                }
-            });
+            let mut engine = None;
+            match &args.engine {
+                Some(command) => match Engine::start(command, tx) {
+                    Ok(started) => engine = Some(started),
+                    Err(e) => {
+                        println!("Unable to start the engine. {e}");
+                        return;
+                    },
+                },
+                None => {
+                    thread::spawn(move|| {
+                        loop {
+                            let mut send = String::new();
+                            io::stdin().read_line(&mut send).unwrap();
+                            tx.send(send).unwrap();
+                        }
+                    });
+                },
+            }
 
-            // set underlying stream to nonblocking mode
-            match socket.get_mut() {
-                tungstenite::stream::MaybeTlsStream::Plain(stream) => stream.set_nonblocking(true).unwrap(),
-                _ => unimplemented!(),
+            // wait up to a tick for the server before checking
+            // for input, so the loop sleeps rather than spins
+            if let Err(e) = bot::tcp(&socket).set_read_timeout(Some(common::THREAD_SLEEP)) {
+                println!("Unable to wait for the server. {e}");
+                return;
             }
 
             // initialise dummy state
@@ -47,25 +234,75 @@ fn main() {
             loop {
                 match socket.read_message() {
                     Ok(msg) if msg.is_binary() => {
-                        let msg: Message = msg.into_data().into();
-
-                        if let Some(msg) = play(msg, &mut state, &rx) {
-                            socket.write_message(tungstenite::Message::binary(msg)).unwrap()
+                        let msg = common::Message::try_from(msg.into_data());
+                        if let (Ok(common::Message::Server(msg)), Some(path)) = (&msg, &args.session) {
+                            match msg {
+                                ServerMessage::LoggedIn { username, token } => {
+                                    save_session(path, username, token);
+                                    resuming = false;
+                                },
+                                ServerMessage::AccountError(_) if resuming => {
+                                    // the saved session has expired, ask for the password next time
+                                    let _ = fs::remove_file(path);
+                                    resuming = false;
+                                },
+                                _ => (),
+                            }
+                        }
+                        match msg {
+                            Ok(common::Message::TicTacToe(msg)) => {
+                                play(msg.clone(), &mut state);
+                                if let Some(reply) = engine.as_mut().and_then(|engine| engine.update(&msg, &state)) {
+                                    send(&mut socket, reply);
+                                }
+                            },
+                            Ok(common::Message::Server(ServerMessage::AccountError(e))) if engine.is_some() => {
+                                // an engine is only run on the account it was given,
+                                // it shouldn't go on to play as a guest instead
+                                println!("Unable to log in. {e}");
+                                drop(engine);
+                                process::exit(1);
+                            },
+                            Ok(common::Message::Server(msg)) => {
+                                let login_handled = matches!(msg, ServerMessage::LoggedIn { .. } | ServerMessage::AccountError(_));
+                                server_message(msg, &args);
+                                if login_handled && !queued {
+                                    send(&mut socket, find_game.clone());
+                                    queued = true;
+                                }
+                            },
+                            Ok(common::Message::Client(_)) => (), // only sent by clients
+                            Err(e) => println!("Invalid message from the server. {e}"),
                         }
                     },
                     Ok(msg) => {
+                        if let tungstenite::Message::Close(Some(frame)) = &msg {
+                            println!("Disconnected: {}", frame.reason);
+                        }
                         if msg.is_close() {
                             break; // exit the thread if close msg received
                         } else if msg.is_text() {
                             println!("text msg received: {msg}");
                         }
                     },
-                    Err(tungstenite::Error::Io(_)) => continue, // read timeout
+                    // nothing arrived within the tick, `TimedOut` on windows
+                    Err(tungstenite::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
                     Err(e) => {
                         println!("{e}");
                         break;
                     },
                 }
+
+                // moves and chat typed by the player, or the engine's moves
+                if let Ok(line) = rx.try_recv() {
+                    let msg = match &mut engine {
+                        Some(engine) => engine.input(&line, &mut state).map(common::Message::from),
+                        None => input(&line, &mut state),
+                    };
+                    if let Some(msg) = msg {
+                        send(&mut socket, msg);
+                    }
+                }
             }
 
             println!("Connection lost");
@@ -76,7 +313,36 @@ fn main() {
     }
 }
 
-fn play(msg: Message, state: &mut ClientState, rx: &Receiver<String>) -> Option<Message> {
+/// Reads the token saved by [`save_session`], if it belongs to `username`.
+fn load_session(path: &Path, username: &str) -> Option<String> {
+    let saved = fs::read_to_string(path).ok()?;
+    let (saved_username, token) = saved.trim_end().split_once('\n')?;
+    (saved_username == username).then(|| token.to_string())
+}
+
+/// Saves the session token so it can be resumed by the next run. The
+/// file stands in for the password, so only its owner can read it.
+fn save_session(path: &Path, username: &str, token: &str) {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+
+    match options.open(path).and_then(|mut file| writeln!(file, "{username}\n{token}")) {
+        Ok(()) => (),
+        Err(e) => println!("Unable to save the session to {}. {e}", path.display()),
+    }
+}
+
+/// Sends a message to the server.
+fn send(socket: &mut bot::Socket, msg: impl Into<common::Message>) {
+    let msg: Vec<u8> = msg.into().into();
+    if let Err(e) = socket.write_message(tungstenite::Message::binary(msg)) {
+        println!("Unable to send message. {e}");
+    }
+}
+
+fn play(msg: Message, state: &mut ClientState) {
     match msg {
         Message::Preamble(config) => {
             *state = config;
@@ -90,81 +356,395 @@ fn play(msg: Message, state: &mut ClientState, rx: &Receiver<String>) -> Option<
 
             println!("=====================");
             println!("{}", tic_tac_toe::NAME);
-            println!("Playing with {}", state.opponent);
+            println!("Playing with {}", with_rating(&state.opponent, state.opponent_rating));
             println!("=====================\n");
             println!("Instructions{}", tic_tac_toe::INSTRUCTIONS);
+            println!("Type /resign to give up, /draw to offer a draw or /takeback to undo your last move.\n");
+            if let Some(rating) = state.rating {
+                println!("Your rating is {rating}.");
+            }
+            if let Some(time_control) = state.time_control {
+                println!("Time control: {time_control}");
+            }
             println!("You are player {}. You go {}.\n", state.piece, order);
             print!("{}", state.board);
-
-            None
         },
-        Message::WaitTurn => {
-            println!("Please wait for your opponent to move");
-            None
+        Message::WaitTurn(clocks) => {
+            state.turn = Turn::TurnWait;
+            if let Some(clocks) = clocks {
+                println!("Time left: X {}, O {}", common::format_clock(clocks.crosses), common::format_clock(clocks.noughts));
+            }
+            // spectators are sent this every turn
+            if state.piece != Piece::Empty {
+                println!("Please wait for your opponent to move");
+            }
         },
-        Message::YourTurn => {
+        Message::YourTurn(clocks) => {
+            state.turn = Turn::TurnStart;
+            if let Some(clocks) = clocks {
+                println!("You have {} to move", common::format_clock(clocks.get(&state.piece)));
+            }
             print!("Enter your move (two coordinates, e.g. a 2)\n{}: ", state.piece);
             stdout().flush().unwrap();
-
-            // receive user input from the first thread
-            loop {
-                // block while waiting, otherwise try_recv()
-                let input = rx.recv().unwrap();
-
-                let parse = input
-                    .split_ascii_whitespace()
-                    .map(|s| s.chars().collect())
-                    .collect::<Vec<Vec<char>>>();
-
-                let offset_lower = 97usize; // ascii value for 'a'
-                let offset_upper = 65usize; // ascii value for 'A'
-
-                if parse.len() == 2 {
-                    // assume lhs is a single char
-                    let y: u32 = parse[0][0].into(); // get ord of char
-                    let y = y as usize; // for the following comparisons
-                    match parse[1].iter().collect::<String>().parse::<usize>() {
-                        //  validate y coord in match guard
-                        Ok(x) if x > 0 && x <= state.board.size => {
-                            // less the offset for the decimal value, e.g. a:1, b:2, etc
-                            // less 1 from y to account for zero-indexed board
-                            if y >= offset_lower && y <= offset_lower + state.board.size {
-                                return Some(Message::Move((state.piece.clone(), x-1, y-offset_lower)));
-                            } else if y >= offset_upper && x <= offset_upper + state.board.size {
-                                return Some(Message::Move((state.piece.clone(), x-1, y-offset_upper)));
-                            }
-                        },
-                        _ => (),
-                    };
-                }
-
-                println!("Invalid input. Please enter valid cell coordinates");
-            }
         },
         Message::Move((p, x, y)) => {
             // update board state
             // move has already been validated by server
             state.board.place(p, x, y);
+            state.offer = None;
             print!("{}", state.board);
-            None
         },
         Message::InvalidMove(e) => {
             println!("{e}");
-            play(Message::YourTurn, state, rx)
+            play(Message::YourTurn(None), state)
+        },
+        Message::Spectate(spectate) => {
+            // spectators don't have a piece of their own
+            *state = ClientState::new(String::new(), Piece::Empty, spectate.board.size);
+            state.board = spectate.board;
+            state.turn = Turn::TurnWait;
+
+            println!("=====================");
+            println!("{}", tic_tac_toe::NAME);
+            println!("Watching X: {} vs O: {}",
+                with_rating(&spectate.players[0], spectate.ratings[0]),
+                with_rating(&spectate.players[1], spectate.ratings[1]),
+            );
+            println!("=====================\n");
+            if let Some(clocks) = spectate.clocks {
+                println!("Time left: X {}, O {}", common::format_clock(clocks.crosses), common::format_clock(clocks.noughts));
+            }
+            println!("It is {}'s turn.\n", spectate.current_player);
+            print!("{}", state.board);
         },
+        Message::Spectators(n) => println!("Spectators watching: {n}"),
         Message::GameOver(end) => {
+            state.turn = Turn::End;
+            state.offer = None;
+            if state.piece == Piece::Empty {
+                // spectating
+                match end {
+                    End::Victory(p) => println!("{p} has won the game"),
+                    End::Draw => println!("The game has ended in a draw"),
+                    End::Disconnect => println!("A player has disconnected, the game is over"),
+                    End::Timeout(p) => println!("{p} ran out of time and lost the game"),
+                    End::Resignation(p) => println!("{p} resigned and lost the game"),
+                    End::AgreedDraw => println!("The players agreed to a draw"),
+                    End::Aborted => println!("The game was stopped by the server"),
+                }
+                return;
+            }
             match end {
-                End::Disconnect => println!("Opponent has disconnected. Exiting session and returning to lobby"),
-                End::Victory(p) if p == state.piece => println!("Congratualtions you have won!\nThe session will end and you will be returned to the lobby"),
-                End::Victory(p) if p != state.piece => println!("You lose!\nThe session will end and you will be returned to the lobby"),
-                End::Draw => println!("The game has ended in a draw! There are no winners.\nThe session will end and you will be returned to the lobby"),
-                _ => unreachable!(),
+                End::Timeout(p) if p == state.piece => println!("You ran out of time, you lose!"),
+                End::Timeout(_) => println!("Your opponent ran out of time, you win!"),
+                End::Resignation(p) if p == state.piece => println!("You resigned."),
+                End::Resignation(_) => println!("Your opponent resigned, you win!"),
+                End::Disconnect => {
+                    println!("Opponent has disconnected. Exiting session and returning to lobby");
+                    state.turn = Turn::Begin;
+                    return;
+                },
+                End::Aborted => {
+                    println!("The server stopped the game before it finished, it won't be rated");
+                    state.turn = Turn::Begin;
+                    return;
+                },
+                End::Victory(p) if p == state.piece => println!("Congratualtions you have won!"),
+                End::Victory(_) => println!("You lose!"),
+                End::Draw => println!("The game has ended in a draw! There are no winners."),
+                End::AgreedDraw => println!("You agreed to a draw."),
+            }
+            println!("Type /rematch to play again or /decline to return to the lobby");
+        },
+        Message::Offer(offer) => {
+            state.offer = Some(offer);
+            match offer {
+                Offer::Draw => println!("Your opponent offers a draw. Type /accept or /decline"),
+                Offer::Takeback => println!("Your opponent asks to take back their last move. Type /accept or /decline"),
+                Offer::Rematch => println!("Your opponent wants a rematch. Type /accept or /decline"),
+            }
+        },
+        Message::Answer(offer, _) => match offer {
+            Offer::Draw => println!("Your opponent declined the draw"),
+            Offer::Takeback => println!("Your opponent declined the takeback"),
+            Offer::Rematch => {
+                state.turn = Turn::Begin;
+                state.offer = None;
+                println!("No rematch, returning to the lobby");
+            },
+        },
+        Message::TakeBack(moves) => {
+            state.offer = None;
+            for (_, x, y) in moves {
+                state.board.place(Piece::Empty, x, y);
             }
-            None
+            println!("The last move was taken back");
+            print!("{}", state.board);
+        },
+        // only sent by clients
+        Message::Resign => (),
+    }
+}
+
+/// Handles a line typed by the player, returning the message to send if any.
+/// Lines starting with `/say` are sent to the current game, or the lobby
+/// if there isn't one, and `/lobby` always goes to the lobby. During a game
+/// players can also `/resign`, offer a `/draw`, ask to `/takeback` their
+/// last move and `/accept` or `/decline` their opponent's offers. Once the
+/// game is over they can ask for a `/rematch` or `/decline` to play again.
+/// A game waiting to carry on after a restart can be given up with `/abandon`.
+fn input(line: &str, state: &mut ClientState) -> Option<common::Message> {
+    let line = line.trim();
+    let in_game = matches!(state.turn, Turn::TurnStart | Turn::TurnWait);
+    let playing = in_game && state.piece != Piece::Empty;
+    let finished = state.turn == Turn::End && state.piece != Piece::Empty;
+
+    if let Some(text) = line.strip_prefix("/lobby ") {
+        Some(ClientMessage::Chat(ChatChannel::Lobby, text.to_string()).into())
+    } else if let Some(text) = line.strip_prefix("/say ") {
+        let channel = if in_game { ChatChannel::Game } else { ChatChannel::Lobby };
+        Some(ClientMessage::Chat(channel, text.to_string()).into())
+    } else if !in_game && line == "/abandon" {
+        Some(ClientMessage::AbandonGame.into())
+    } else if playing && line == "/resign" {
+        Some(Message::Resign.into())
+    } else if playing && line == "/draw" {
+        Some(Message::Offer(Offer::Draw).into())
+    } else if playing && line == "/takeback" {
+        Some(Message::Offer(Offer::Takeback).into())
+    } else if finished && line == "/rematch" {
+        Some(Message::Offer(Offer::Rematch).into())
+    } else if line == "/accept" || line == "/decline" {
+        match state.offer.take() {
+            Some(offer) => Some(Message::Answer(offer, line == "/accept").into()),
+            None if finished && line == "/decline" => Some(Message::Answer(Offer::Rematch, false).into()),
+            None => {
+                println!("There is no offer to answer");
+                None
+            },
+        }
+    } else if state.turn == Turn::TurnStart {
+        match parse_move(line, state) {
+            Some(msg) => {
+                state.turn = Turn::TurnWait;
+                Some(msg.into())
+            },
+            None => {
+                println!("Invalid input. Please enter valid cell coordinates");
+                None
+            },
         }
-        Message::Status(status) => {
-            println!("Players online: {}", status.n_players);
-            None
+    } else {
+        if !line.is_empty() {
+            println!("Wait for your turn to move, or chat with /say <message>");
         }
+        None
+    }
+}
+
+fn parse_move(input: &str, state: &ClientState) -> Option<Message> {
+    let parse = input
+        .split_ascii_whitespace()
+        .map(|s| s.chars().collect())
+        .collect::<Vec<Vec<char>>>();
+
+    let offset_lower = 97usize; // ascii value for 'a'
+    let offset_upper = 65usize; // ascii value for 'A'
+
+    if parse.len() == 2 {
+        // assume lhs is a single char
+        let y: u32 = parse[0][0].into(); // get ord of char
+        let y = y as usize; // for the following comparisons
+        match parse[1].iter().collect::<String>().parse::<usize>() {
+            //  validate y coord in match guard
+            Ok(x) if x > 0 && x <= state.board.size => {
+                // less the offset for the decimal value, e.g. a:1, b:2, etc
+                // less 1 from y to account for zero-indexed board
+                if y >= offset_lower && y < offset_lower + state.board.size {
+                    return Some(Message::Move((state.piece.clone(), x-1, y-offset_lower)));
+                } else if y >= offset_upper && y < offset_upper + state.board.size {
+                    return Some(Message::Move((state.piece.clone(), x-1, y-offset_upper)));
+                }
+            },
+            _ => (),
+        };
+    }
+
+    None
+}
+
+fn server_message(msg: ServerMessage, args: &Args) {
+    match msg {
+        ServerMessage::Ping(status) => println!("Players online: {}", status.n_players),
+        ServerMessage::LoggedIn { username, .. } => println!("Logged in as {username}"),
+        ServerMessage::LoggedOut => println!("Logged out, playing as a guest"),
+        ServerMessage::AccountError(e) => println!("{e}"),
+        ServerMessage::Error(e) => println!("Server error: {e}"),
+        ServerMessage::Shutdown(remaining) => println!("The server is shutting down, games in progress have {} left to finish", common::format_clock(remaining)),
+        ServerMessage::StoppedSpectating => println!("Stopped watching"),
+        ServerMessage::Suspended(game) => println!("Your game of {} has been saved, it carries on when you both log back in after the restart", game.name()),
+        ServerMessage::Resuming(game, opponent) => println!("Your game of {} will carry on once {opponent} is back, or /abandon it to find a new game", game.name()),
+        ServerMessage::Leaderboard(game, rankings) => {
+            println!("{} leaderboard", game.name());
+            if rankings.is_empty() { println!("  No rated games yet"); }
+            for (i, ranking) in rankings.iter().enumerate() {
+                println!("{:>3}. {:<20} {:>5}  ({} games)", i + 1, ranking.username, ranking.rating, ranking.games);
+            }
+        },
+        ServerMessage::RatingChange { game, rating, change } => {
+            println!("Your {} rating is now {rating} ({change:+})", game.name());
+        },
+        ServerMessage::History(games) => {
+            println!("Recent games");
+            if games.is_empty() { println!("  None"); }
+            for game in games {
+                println!("{:>5}  {}  {} {}  {} vs {}  {}",
+                    game.id,
+                    history::format_time(game.started),
+                    game.game.name(), game.variant,
+                    game.players[0], game.players[1],
+                    game.result_text(),
+                );
+            }
+        },
+        ServerMessage::GameRecord(record) => {
+            if let Some(path) = &args.export {
+                match fs::write(path, record.export()) {
+                    Ok(()) => println!("Saved game {} to {}", record.summary.id, path.display()),
+                    Err(e) => println!("Unable to save {}. {e}", path.display()),
+                }
+            }
+            print_record(record);
+        },
+        ServerMessage::Chat(ChatMessage { channel, from, text }) => match channel {
+            ChatChannel::Lobby => println!("[lobby] {from}: {text}"),
+            ChatChannel::Game => println!("[game] {from}: {text}"),
+        },
+        ServerMessage::LiveGames(games) => {
+            println!("Live games");
+            if games.is_empty() { println!("  None"); }
+            for game in games {
+                println!("{:>5}  {}  {} vs {}  ({} watching)",
+                    game.id,
+                    game.game.name(),
+                    game.players[0], game.players[1],
+                    game.spectators,
+                );
+            }
+        },
+    }
+}
+
+fn print_summary(summary: &GameSummary) {
+    println!("=====================");
+    println!("{} {}", summary.game.name(), summary.variant);
+    println!("{} vs {}", summary.players[0], summary.players[1]);
+    println!("{} to {}", history::format_time(summary.started), history::format_time(summary.ended));
+    println!("=====================\n");
+}
+
+fn print_record(GameRecord { summary, moves }: GameRecord) {
+    print_summary(&summary);
+
+    let size = tic_tac_toe::parse_variant(&summary.variant).unwrap_or(tic_tac_toe::BOARD_SIZE);
+    let mut board = Board::new(size);
+    for (i, (p, x, y)) in moves.into_iter().enumerate() {
+        println!("{}. {p} {} {}", i + 1, (y as u8 + b'A') as char, x + 1);
+        board.place(p, x, y);
+    }
+    print!("\n{board}");
+    println!("{}", summary.result_text());
+}
+
+fn with_rating(name: &str, rating: Option<u32>) -> String {
+    match rating {
+        Some(rating) => format!("{name} ({rating})"),
+        None => name.to_string(),
+    }
+}
+
+/// Plays a game at the terminal, with the computer taking the noughts if
+/// there is a difficulty. Uses the same rules and input as online games.
+fn local(bot: Option<Difficulty>) {
+    let mut state = ClientState::new(String::new(), Piece::Cross, tic_tac_toe::BOARD_SIZE);
+    state.board = Board::new(state.board.size);
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    let mut ai = bot.map(|difficulty| Ai::new(difficulty, seed));
+
+    println!("=====================");
+    println!("{}", tic_tac_toe::NAME);
+    match bot {
+        Some(difficulty) => println!("Playing the computer ({difficulty})"),
+        None => println!("Two players at this terminal"),
+    }
+    println!("=====================\n");
+    println!("Instructions{}", tic_tac_toe::INSTRUCTIONS);
+    print!("{}", state.board);
+
+    let end = loop {
+        let (x, y) = match &mut ai {
+            Some(ai) if state.piece == Piece::Nought => {
+                let Some((x, y)) = ai.choose_move(&state.board, &state.piece) else { break End::Draw };
+                println!("The computer plays {} {}", (y as u8 + b'A') as char, x + 1);
+                (x, y)
+            },
+            _ => {
+                print!("{}: ", state.piece);
+                stdout().flush().unwrap();
+                let mut line = String::new();
+                if io::stdin().read_line(&mut line).unwrap() == 0 {
+                    return;
+                }
+                match parse_move(&line, &state) {
+                    Some(Message::Move((_, x, y))) => (x, y),
+                    _ => {
+                        println!("Invalid input. Please enter valid cell coordinates");
+                        continue;
+                    },
+                }
+            },
+        };
+
+        if let Err(e) = state.board.try_place(state.piece.clone(), x, y) {
+            println!("{e}");
+            continue;
+        }
+        print!("{}", state.board);
+        if let Some(end) = state.board.check_victory(state.piece.clone()) {
+            break end;
+        }
+        state.piece = state.piece.clone().next();
+    };
+
+    match end {
+        End::Victory(p) => println!("{p} has won the game"),
+        _ => println!("The game has ended in a draw"),
+    }
+}
+
+/// Replays a game from a file one move at a time, waiting for enter between moves.
+fn replay(path: &Path) {
+    let record = match fs::read_to_string(path).map_err(|e| e.to_string()).and_then(|text| GameRecord::import(&text)) {
+        Ok(record) => record,
+        Err(e) => {
+            println!("Unable to read {}. {e}", path.display());
+            return;
+        },
+    };
+
+    print_summary(&record.summary);
+    let size = tic_tac_toe::parse_variant(&record.summary.variant).unwrap_or(tic_tac_toe::BOARD_SIZE);
+    let mut board = Board::new(size);
+    print!("{board}");
+
+    for (i, (p, x, y)) in record.moves.into_iter().enumerate() {
+        print!("Press enter for the next move");
+        stdout().flush().unwrap();
+        io::stdin().read_line(&mut String::new()).unwrap();
+
+        println!("{}. {p} {} {}", i + 1, (y as u8 + b'A') as char, x + 1);
+        board.place(p, x, y);
+        print!("{board}");
     }
+    println!("{}", record.summary.result_text());
 }
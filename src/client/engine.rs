@@ -0,0 +1,167 @@
+//! Line based protocol for bots, loosely based on the UCI protocol used by
+//! chess engines. The engine is a separate program started by the client,
+//! reading commands on stdin and replying on stdout, one per line.
+//!
+//! Commands sent to the engine:
+//! - `protocol 1` once it has started
+//! - `newgame tictactoe 3x3 X` with the game, variant and the engine's piece
+//! - `move O B2` for every move played, including the engine's own
+//! - `takeback B2` for each move removed from the board
+//! - `go 29500` when it is the engine's turn, with the milliseconds left on
+//!   its clock if the game has a time control
+//! - `invalid <reason>` if the last move was refused, followed by `go`
+//! - `gameover win`, `loss`, `draw`, or `aborted` if the server stopped it
+//! - `quit` before the client exits
+//!
+//! Replies read from the engine:
+//! - `bestmove B2` with the engine's move, only after `go`
+//! - `id name <name>` optionally, after `protocol`
+//! - `info <text>` for anything to show the person running the client
+//!
+//! Draw and takeback offers are declined for the engine, as are rematches
+//! so that it goes back to the matchmaking queue after every game.
+
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::mpsc::Sender,
+    thread,
+};
+
+use common::history;
+use common::tic_tac_toe::{self, ClientState, End, Message, Offer, Piece, Turn};
+
+/// Version sent in the `protocol` command.
+const PROTOCOL_VERSION: u32 = 1;
+
+pub struct Engine {
+    child: Child,
+    stdin: ChildStdin,
+}
+
+impl Engine {
+    /// Starts the engine from a command line, e.g. `python3 bot.py`.
+    /// Each line it writes is sent through `lines`.
+    pub fn start(command: &str, lines: Sender<String>) -> io::Result<Self> {
+        let mut args = command.split_whitespace();
+        let program = args.next().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty engine command"))?;
+        let mut child = Command::new(program)
+            .args(args)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()?;
+
+        let stdin = child.stdin.take().unwrap();
+        let stdout = BufReader::new(child.stdout.take().unwrap());
+        thread::spawn(move|| {
+            // stops when the engine exits or the client does
+            for line in stdout.lines().map_while(Result::ok) {
+                if lines.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut engine = Engine { child, stdin };
+        engine.send(&format!("protocol {PROTOCOL_VERSION}"));
+        Ok(engine)
+    }
+
+    fn send(&mut self, command: &str) {
+        if let Err(e) = writeln!(self.stdin, "{command}") {
+            eprintln!("Unable to send `{command}` to the engine. {e}");
+        }
+    }
+
+    /// Tells the engine about a message from the server, after it has
+    /// been applied to `state`. Returns the reply to send, e.g. to an offer.
+    pub fn update(&mut self, msg: &Message, state: &ClientState) -> Option<Message> {
+        match msg {
+            Message::Preamble(state) => {
+                let variant = tic_tac_toe::variant(state.board.size);
+                self.send(&format!("newgame tictactoe {variant} {}", state.piece));
+            },
+            Message::YourTurn(clocks) => match clocks {
+                Some(clocks) => self.send(&format!("go {}", clocks.get(&state.piece).as_millis())),
+                None => self.send("go"),
+            },
+            Message::InvalidMove(reason) => {
+                self.send(&format!("invalid {reason}"));
+                self.send("go");
+            },
+            Message::Move((p, x, y)) => self.send(&format!("move {p} {}", history::coordinates(*x, *y))),
+            Message::TakeBack(moves) => {
+                for (_, x, y) in moves {
+                    self.send(&format!("takeback {}", history::coordinates(*x, *y)));
+                }
+            },
+            Message::Offer(offer) => return Some(Message::Answer(*offer, false)),
+            Message::GameOver(end) => {
+                let result = match end {
+                    End::Victory(p) | End::Timeout(p) | End::Resignation(p) => {
+                        // the piece is the winner for a victory but the loser otherwise
+                        let won = (p == &state.piece) == matches!(end, End::Victory(_));
+                        if won { "win" } else { "loss" }
+                    },
+                    // the opponent left
+                    End::Disconnect => "win",
+                    End::Draw | End::AgreedDraw => "draw",
+                    End::Aborted => "aborted",
+                };
+                self.send(&format!("gameover {result}"));
+                if !matches!(end, End::Disconnect | End::Aborted) {
+                    // straight back to the queue
+                    return Some(Message::Answer(Offer::Rematch, false));
+                }
+            },
+            _ => (),
+        }
+        None
+    }
+
+    /// Handles a line written by the engine, returning the move to send if any.
+    pub fn input(&mut self, line: &str, state: &mut ClientState) -> Option<Message> {
+        let (command, rest) = line.trim().split_once(' ').unwrap_or((line.trim(), ""));
+        match command {
+            "bestmove" if state.turn == Turn::TurnStart && state.piece != Piece::Empty => {
+                match history::parse_coordinates(rest.trim()) {
+                    Some((x, y)) if x < state.board.size && y < state.board.size => {
+                        state.turn = Turn::TurnWait;
+                        Some(Message::Move((state.piece.clone(), x, y)))
+                    },
+                    _ => {
+                        self.send(&format!("invalid {rest} isn't on the board"));
+                        self.send("go");
+                        None
+                    },
+                }
+            },
+            "bestmove" => {
+                println!("Engine moved out of turn: {rest}");
+                None
+            },
+            "id" => {
+                if let Some(name) = rest.strip_prefix("name ") {
+                    println!("Engine: {name}");
+                }
+                None
+            },
+            "info" => {
+                println!("Engine info: {rest}");
+                None
+            },
+            "" => None,
+            _ => {
+                println!("Unknown engine command: {line}");
+                None
+            },
+        }
+    }
+}
+
+impl Drop for Engine {
+    fn drop(&mut self) {
+        self.send("quit");
+        let _ = self.child.wait();
+    }
+}
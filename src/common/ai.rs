@@ -0,0 +1,273 @@
+use serde::{Serialize, Deserialize};
+use std::{fmt, str::FromStr};
+
+use crate::tic_tac_toe::{Board, End, Piece};
+
+/// How hard a computer opponent plays.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Difficulty {
+    /// Any empty cell.
+    Random,
+    /// Wins or blocks a win when it can, otherwise random.
+    Greedy,
+    /// Searches ahead as far as it can in a fixed number of
+    /// positions, it never loses on the standard board.
+    Minimax,
+}
+
+impl Difficulty {
+    pub const ALL: [Difficulty; 3] = [Difficulty::Random, Difficulty::Greedy, Difficulty::Minimax];
+}
+
+impl fmt::Display for Difficulty {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Difficulty::Random => write!(f, "random"),
+            Difficulty::Greedy => write!(f, "greedy"),
+            Difficulty::Minimax => write!(f, "minimax"),
+        }
+    }
+}
+
+impl FromStr for Difficulty {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Difficulty::ALL
+            .into_iter()
+            .find(|difficulty| difficulty.to_string() == s.to_lowercase())
+            .ok_or_else(|| format!("Invalid difficulty {s}, expected random, greedy or minimax"))
+    }
+}
+
+/// Score of a win, reduced by the number of
+/// moves it takes so quicker wins are preferred.
+const WIN: i32 = 1000;
+
+/// Positions minimax may look at for each move. The search goes one move
+/// deeper at a time, keeping the deepest search that finished, so the
+/// standard board is solved while larger ones stop early and stay quick.
+const NODE_BUDGET: usize = 200_000;
+
+/// Computer player for tic tac toe.
+pub struct Ai {
+    pub difficulty: Difficulty,
+    rng: Rng,
+}
+
+impl Ai {
+    /// The seed picks between equally good moves,
+    /// e.g. the current time for a different game each time.
+    pub fn new(difficulty: Difficulty, seed: u64) -> Self {
+        Ai {
+            difficulty,
+            rng: Rng::new(seed),
+        }
+    }
+
+    /// Chooses the x, y coordinates of the next move for `piece`,
+    /// or `None` if the board is full.
+    pub fn choose_move(&mut self, board: &Board, piece: &Piece) -> Option<(usize, usize)> {
+        let cells = board.empty_cells();
+        if cells.is_empty() {
+            return None;
+        }
+
+        let best = match self.difficulty {
+            Difficulty::Random => cells,
+            Difficulty::Greedy => greedy(board, piece, cells),
+            Difficulty::Minimax => minimax(board, piece, cells),
+        };
+        Some(best[self.rng.below(best.len())])
+    }
+}
+
+/// Winning moves if there are any, then moves that stop the
+/// opponent winning next turn, then the centre of the board.
+fn greedy(board: &Board, piece: &Piece, cells: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let opponent = piece.clone().next();
+    let completes = |p: &Piece| -> Vec<(usize, usize)> {
+        cells.iter().copied().filter(|&(x, y)| {
+            let mut board = board.clone();
+            board.place(p.clone(), x, y);
+            matches!(board.check_victory(p.clone()), Some(End::Victory(_)))
+        }).collect()
+    };
+
+    let wins = completes(piece);
+    if !wins.is_empty() {
+        return wins;
+    }
+    let blocks = completes(&opponent);
+    if !blocks.is_empty() {
+        return blocks;
+    }
+    let centre = (board.size / 2, board.size / 2);
+    if board.size % 2 == 1 && cells.contains(&centre) {
+        return vec![centre];
+    }
+    cells
+}
+
+/// Every move with the best score after searching the game tree
+/// as deep as the budget allows.
+fn minimax(board: &Board, piece: &Piece, cells: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let mut board = board.clone();
+    let mut search = Search { max_depth: 0, nodes: 0 };
+    let mut best = cells.clone();
+    // the whole tree has been searched once the depth reaches the empty cells
+    for max_depth in 1..=cells.len() {
+        search.max_depth = max_depth;
+        let scores: Option<Vec<i32>> = cells.iter().map(|&(x, y)| {
+            search.score_move(&mut board, piece, (x, y), 0, -WIN - 1, WIN + 1)
+        }).collect();
+        let Some(scores) = scores else { break };
+
+        let top = scores.iter().copied().max().unwrap_or_default();
+        best = cells.iter().copied().zip(scores).filter(|(_, score)| *score == top).map(|(cell, _)| cell).collect();
+    }
+    best
+}
+
+/// A search limited to `max_depth` moves ahead, which gives
+/// up once it has looked at more than [`NODE_BUDGET`] positions.
+struct Search {
+    max_depth: usize,
+    nodes: usize,
+}
+
+impl Search {
+    /// Score of `piece` moving at the x, y cell from the point of view
+    /// of `piece`, or `None` if the budget ran out.
+    fn score_move(&mut self, board: &mut Board, piece: &Piece, (x, y): (usize, usize), depth: usize, alpha: i32, beta: i32) -> Option<i32> {
+        self.nodes += 1;
+        if self.nodes > NODE_BUDGET {
+            return None;
+        }
+        board.place(piece.clone(), x, y);
+        let score = match board.check_victory(piece.clone()) {
+            Some(End::Victory(_)) => Some(WIN - depth as i32),
+            Some(_) => Some(0),
+            // positions past the depth are scored as even
+            None if depth + 1 >= self.max_depth => Some(0),
+            None => self.negamax(board, &piece.clone().next(), depth + 1, -beta, -alpha).map(|score| -score),
+        };
+        // undone even when giving up so the board is left as it was
+        board.place(Piece::Empty, x, y);
+        score
+    }
+
+    /// Best score for `piece` to move next, using alpha-beta pruning.
+    fn negamax(&mut self, board: &mut Board, piece: &Piece, depth: usize, mut alpha: i32, beta: i32) -> Option<i32> {
+        let mut best = -WIN - 1;
+        for cell in board.empty_cells() {
+            let score = self.score_move(board, piece, cell, depth, alpha, beta)?;
+            best = best.max(score);
+            alpha = alpha.max(score);
+            if alpha >= beta {
+                break;
+            }
+        }
+        Some(best)
+    }
+}
+
+/// Small xorshift generator, good enough to vary the computer's
+/// moves while keeping `common` free of platform specific crates.
+struct Rng(u64);
+
+impl Rng {
+    fn new(seed: u64) -> Self {
+        // xorshift gets stuck on zero
+        Rng(seed | 1)
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Random number in the range `0..n`.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Board with crosses and noughts on the given x, y cells.
+    fn board(crosses: &[(usize, usize)], noughts: &[(usize, usize)]) -> Board {
+        let mut board = Board::new(3);
+        for &(x, y) in crosses { board.place(Piece::Cross, x, y); }
+        for &(x, y) in noughts { board.place(Piece::Nought, x, y); }
+        board
+    }
+
+    /// Plays every move the opponent could make against every move minimax
+    /// thinks is best, failing if the opponent ever wins.
+    fn never_loses(board: &mut Board, ai: &Piece, turn: &Piece) {
+        let cells = board.empty_cells();
+        let moves = if turn == ai { minimax(board, turn, cells) } else { cells };
+        for (x, y) in moves {
+            board.place(turn.clone(), x, y);
+            match board.check_victory(turn.clone()) {
+                Some(End::Victory(winner)) => assert_eq!(&winner, ai, "lost on {board:?}"),
+                Some(_) => (),
+                None => never_loses(board, ai, &turn.clone().next()),
+            }
+            board.place(Piece::Empty, x, y);
+        }
+    }
+
+    #[test]
+    fn takes_an_immediate_win() {
+        let board = board(&[(0, 0), (1, 0)], &[(0, 1), (1, 1)]);
+        for difficulty in [Difficulty::Greedy, Difficulty::Minimax] {
+            for seed in 0..10 {
+                let mut ai = Ai::new(difficulty, seed);
+                assert_eq!(ai.choose_move(&board, &Piece::Cross), Some((2, 0)), "{difficulty} missed the win");
+            }
+        }
+    }
+
+    #[test]
+    fn blocks_an_immediate_loss() {
+        let board = board(&[(0, 0), (1, 0)], &[(1, 1)]);
+        for difficulty in [Difficulty::Greedy, Difficulty::Minimax] {
+            for seed in 0..10 {
+                let mut ai = Ai::new(difficulty, seed);
+                assert_eq!(ai.choose_move(&board, &Piece::Nought), Some((2, 0)), "{difficulty} didn't block");
+            }
+        }
+    }
+
+    #[test]
+    fn minimax_never_loses_on_the_standard_board() {
+        // going second, against every opening and every reply after it
+        never_loses(&mut Board::new(3), &Piece::Nought, &Piece::Cross);
+        // and going first
+        never_loses(&mut Board::new(3), &Piece::Cross, &Piece::Cross);
+    }
+
+    #[test]
+    fn minimax_draws_against_itself() {
+        for (x, y) in Board::new(3).empty_cells() {
+            let mut board = board(&[(x, y)], &[]);
+            let mut ai = Ai::new(Difficulty::Minimax, (x * 3 + y) as u64);
+            let mut turn = Piece::Nought;
+            let end = loop {
+                let (x, y) = ai.choose_move(&board, &turn).unwrap();
+                board.place(turn.clone(), x, y);
+                if let Some(end) = board.check_victory(turn.clone()) {
+                    break end;
+                }
+                turn = turn.next();
+            };
+            assert!(matches!(end, End::Draw), "{end:?} after opening at {x}, {y}");
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Library for writing bots that play on the server. It handles the
+//! connection, logging in, matchmaking and the game loop, leaving the
+//! bot to choose its moves.
+//!
+//! ```no_run
+//! use common::bot::{self, Config};
+//! use common::tic_tac_toe::ClientState;
+//!
+//! // play the first empty cell every turn
+//! let first_free = |state: &ClientState| {
+//!     let (x, y) = state.board.empty_cells()[0];
+//!     (state.piece.clone(), x, y)
+//! };
+//! bot::play(&Config::default(), first_free).unwrap();
+//! ```
+
+use std::{fs, net::TcpStream, path::{Path, PathBuf}};
+
+use native_tls::{Certificate, TlsConnector};
+use tungstenite::{stream::MaybeTlsStream, Connector, WebSocket};
+
+use crate::{AccountRequest, ClientMessage, Credentials, Game, Message, ServerMessage};
+use crate::tic_tac_toe::{self, Board, ClientState, End, Move, Offer, Piece, Turn};
+
+pub type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// Where and how a bot connects to the server.
+pub struct Config {
+    pub host: String,
+    pub port: u16,
+    /// Account to play as, which is flagged as a bot.
+    /// Bots without one play as guests.
+    pub credentials: Option<Credentials>,
+    /// Create the account before logging in.
+    pub sign_up: bool,
+    /// Stop after this many games, or keep playing if `None`.
+    pub games: Option<usize>,
+    /// Connect with plain websockets, for servers run with `--plain`.
+    pub plain: bool,
+    /// PEM file of an extra certificate authority to trust,
+    /// e.g. the one used to sign a local server's certificate.
+    pub ca: Option<PathBuf>,
+    /// Accept any certificate, e.g. a self signed one for a
+    /// local server. Never use this for a public server.
+    pub insecure: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            host: crate::REMOTE_IP.to_string(),
+            port: crate::REMOTE_PORT,
+            credentials: None,
+            sign_up: false,
+            games: None,
+            plain: false,
+            ca: None,
+            insecure: false,
+        }
+    }
+}
+
+/// Builds the TLS settings for [`connect`], trusting the
+/// certificate authority in the PEM file at `ca` if given.
+pub fn tls(ca: Option<&Path>, insecure: bool) -> Result<TlsConnector, String> {
+    let mut builder = TlsConnector::builder();
+    if let Some(path) = ca {
+        let pem = fs::read(path).map_err(|e| format!("Unable to read {}. {e}", path.display()))?;
+        let cert = Certificate::from_pem(&pem).map_err(|e| format!("Invalid certificate {}. {e}", path.display()))?;
+        builder.add_root_certificate(cert);
+    }
+    builder
+        .danger_accept_invalid_certs(insecure)
+        .danger_accept_invalid_hostnames(insecure)
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+/// Opens a websocket to the server, over TLS unless `tls` is `None`. The
+/// socket is blocking, set the inner stream to nonblocking if needed.
+pub fn connect(host: &str, port: u16, tls: Option<TlsConnector>) -> Result<Socket, String> {
+    let tcp = TcpStream::connect((host, port)).map_err(|e| format!("Unable to connect to {host}:{port}. {e}"))?;
+    let opened = match tls {
+        Some(tls) => tungstenite::client_tls_with_config(format!("wss://{host}:{port}"), tcp, None, Some(Connector::NativeTls(tls))),
+        None => tungstenite::client(format!("ws://{host}:{port}"), MaybeTlsStream::Plain(tcp)),
+    };
+    opened
+        .map(|(socket, _)| socket)
+        .map_err(|e| format!("Unable to open a websocket to {host}:{port}. {e}"))
+}
+
+/// The TCP connection under the websocket, e.g. to set it to nonblocking.
+pub fn tcp(socket: &Socket) -> &TcpStream {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream,
+        MaybeTlsStream::NativeTls(stream) => stream.get_ref(),
+        _ => unreachable!("Only native-tls is enabled"),
+    }
+}
+
+fn send(socket: &mut Socket, msg: impl Into<Message>) -> Result<(), String> {
+    let bytes: Vec<u8> = msg.into().into();
+    socket.write_message(tungstenite::Message::binary(bytes)).map_err(|e| e.to_string())
+}
+
+/// Connects to the server and plays games using `choose_move` until
+/// the number of games in the config have been played, returning how
+/// each one ended. Offers from the opponent are always declined.
+pub fn play(config: &Config, mut choose_move: impl FnMut(&ClientState) -> Move) -> Result<Vec<End>, String> {
+    let tls = if config.plain {
+        None
+    } else {
+        Some(tls(config.ca.as_deref(), config.insecure)?)
+    };
+    let mut socket = connect(&config.host, config.port, tls)?;
+
+    match &config.credentials {
+        Some(credentials) => {
+            let request = if config.sign_up {
+                AccountRequest::SignUp(credentials.clone())
+            } else {
+                AccountRequest::Login(credentials.clone())
+            };
+            send(&mut socket, ClientMessage::Account(request))?;
+            send(&mut socket, ClientMessage::Account(AccountRequest::FlagBot))?;
+        },
+        // guests can look for a game straight away
+        None => send(&mut socket, ClientMessage::FindGame(Game::TicTacToe))?,
+    }
+
+    let mut state = ClientState::new(String::new(), Piece::Empty, 0);
+    let mut results = Vec::new();
+    let mut queued = config.credentials.is_none();
+
+    while config.games.is_none_or(|games| results.len() < games) {
+        let msg = match socket.read_message().map_err(|e| e.to_string())? {
+            msg if msg.is_binary() => bincode::deserialize(&msg.into_data()).map_err(|e| format!("Unable to read a message from the server. {e}"))?,
+            msg if msg.is_close() => return Err("The server closed the connection".to_string()),
+            _ => continue,
+        };
+
+        match msg {
+            Message::Server(ServerMessage::LoggedIn { .. }) if !queued => {
+                send(&mut socket, ClientMessage::FindGame(Game::TicTacToe))?;
+                queued = true;
+            },
+            Message::Server(ServerMessage::AccountError(e)) => return Err(e),
+            Message::Server(ServerMessage::Error(e)) => log::warn!("{e}"),
+            Message::TicTacToe(msg) => match msg {
+                tic_tac_toe::Message::Preamble(config) => {
+                    state = config;
+                    state.board = Board::new(state.board.size);
+                },
+                tic_tac_toe::Message::YourTurn(_) => {
+                    state.turn = Turn::TurnStart;
+                    let (_, x, y) = choose_move(&state);
+                    state.turn = Turn::TurnWait;
+                    send(&mut socket, tic_tac_toe::Message::Move((state.piece.clone(), x, y)))?;
+                },
+                tic_tac_toe::Message::WaitTurn(_) => state.turn = Turn::TurnWait,
+                tic_tac_toe::Message::InvalidMove(e) => return Err(format!("The bot made an invalid move. {e}")),
+                tic_tac_toe::Message::Move((p, x, y)) => state.board.place(p, x, y),
+                tic_tac_toe::Message::TakeBack(moves) => {
+                    for (_, x, y) in moves {
+                        state.board.place(Piece::Empty, x, y);
+                    }
+                },
+                tic_tac_toe::Message::Offer(offer) => send(&mut socket, tic_tac_toe::Message::Answer(offer, false))?,
+                tic_tac_toe::Message::GameOver(end) => {
+                    state.turn = Turn::End;
+                    if !matches!(end, End::Disconnect | End::Aborted) {
+                        // back to the queue for the next game
+                        send(&mut socket, tic_tac_toe::Message::Answer(Offer::Rematch, false))?;
+                    }
+                    results.push(end);
+                },
+                _ => (),
+            },
+            _ => (),
+        }
+    }
+
+    let _ = socket.close(None);
+    Ok(results)
+}
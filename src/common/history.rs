@@ -0,0 +1,368 @@
+use serde::{Serialize, Deserialize};
+
+use crate::Game;
+use crate::tic_tac_toe::{self, Board, End, Move, Piece};
+
+/// Metadata of a finished game.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameSummary {
+    pub id: i64,
+    pub game: Game,
+    /// Game specific rules, e.g. the board size.
+    pub variant: String,
+    /// Player names in the order they move,
+    /// i.e. crosses then noughts.
+    pub players: [String; 2],
+    /// Unix timestamps in seconds.
+    pub started: u64,
+    pub ended: u64,
+    pub result: End,
+}
+
+impl GameSummary {
+    /// Short description of the result using the player names.
+    pub fn result_text(&self) -> String {
+        match &self.result {
+            End::Victory(Piece::Cross) => format!("{} won", self.players[0]),
+            End::Victory(_) => format!("{} won", self.players[1]),
+            End::Draw => "Draw".to_string(),
+            End::Disconnect => "Abandoned".to_string(),
+            End::Timeout(Piece::Cross) => format!("{} won on time", self.players[1]),
+            End::Timeout(_) => format!("{} won on time", self.players[0]),
+            End::Resignation(Piece::Cross) => format!("{} won by resignation", self.players[1]),
+            End::Resignation(_) => format!("{} won by resignation", self.players[0]),
+            End::AgreedDraw => "Draw by agreement".to_string(),
+            End::Aborted => "Aborted".to_string(),
+        }
+    }
+}
+
+/// A finished game with every move played, enough to replay it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct GameRecord {
+    pub summary: GameSummary,
+    pub moves: Vec<Move>,
+}
+
+impl GameRecord {
+    /// Exports the game in a text format based on PGN, the
+    /// portable game notation used for chess. Tags in the header
+    /// hold the metadata, followed by numbered moves using the
+    /// coordinates of the board, i.e. `A1` is the top left cell.
+    ///
+    /// ```text
+    /// [Game "Tic Tac Toe"]
+    /// [Variant "3x3"]
+    /// [Date "2022.10.03"]
+    /// [Time "14:05:00"]
+    /// [Crosses "alice"]
+    /// [Noughts "bob"]
+    /// [Result "1-0"]
+    ///
+    /// 1. A1 B1 2. A2 B2 3. A3 1-0
+    /// ```
+    pub fn export(&self) -> String {
+        let summary = &self.summary;
+        let (result, termination) = match &summary.result {
+            End::Victory(Piece::Cross) => ("1-0", "normal"),
+            End::Victory(_) => ("0-1", "normal"),
+            End::Draw => ("1/2-1/2", "normal"),
+            End::Disconnect => ("*", "abandoned"),
+            End::Timeout(Piece::Cross) => ("0-1", "time forfeit"),
+            End::Timeout(_) => ("1-0", "time forfeit"),
+            // pgn counts these as normal, keep them apart so they can be imported
+            End::Resignation(Piece::Cross) => ("0-1", "resignation"),
+            End::Resignation(_) => ("1-0", "resignation"),
+            End::AgreedDraw => ("1/2-1/2", "agreement"),
+            End::Aborted => ("*", "aborted"),
+        };
+        let (year, month, day) = civil_from_days((summary.started / 86400) as i64);
+        let secs = summary.started % 86400;
+
+        let tags = [
+            ("Game", summary.game.name().to_string()),
+            ("Variant", summary.variant.clone()),
+            ("Date", format!("{year:04}.{month:02}.{day:02}")),
+            ("Time", format!("{:02}:{:02}:{:02}", secs / 3600, secs % 3600 / 60, secs % 60)),
+            ("Duration", summary.ended.saturating_sub(summary.started).to_string()),
+            ("Crosses", summary.players[0].clone()),
+            ("Noughts", summary.players[1].clone()),
+            ("Result", result.to_string()),
+            ("Termination", termination.to_string()),
+            ("GameId", summary.id.to_string()),
+        ];
+
+        let mut out = String::new();
+        for (name, value) in tags {
+            out += &format!("[{name} \"{}\"]\n", value.replace('\\', "\\\\").replace('"', "\\\""));
+        }
+        out += "\n";
+
+        // keep lines short like most pgn files
+        let mut line = String::new();
+        let tokens = self.moves.iter().enumerate().flat_map(|(i, (_, x, y))| {
+            let number = (i % 2 == 0).then(|| format!("{}.", i / 2 + 1));
+            number.into_iter().chain([coordinates(*x, *y)])
+        });
+        for token in tokens.chain([result.to_string()]) {
+            if !line.is_empty() && line.len() + token.len() >= 80 {
+                out += &line;
+                out += "\n";
+                line.clear();
+            }
+            if !line.is_empty() { line += " "; }
+            line += &token;
+        }
+        out += &line;
+        out += "\n";
+        out
+    }
+
+    /// Imports a game exported by [`GameRecord::export`]. Missing tags
+    /// fall back to defaults but every move has to be legal.
+    pub fn import(text: &str) -> Result<Self, String> {
+        let mut summary = GameSummary {
+            id: 0,
+            game: Game::TicTacToe,
+            variant: tic_tac_toe::variant(tic_tac_toe::BOARD_SIZE),
+            players: [String::from("?"), String::from("?")],
+            started: 0,
+            ended: 0,
+            result: End::Disconnect,
+        };
+        let (mut date, mut time, mut duration) = (0, 0, 0);
+        let mut result = String::from("*");
+        let mut termination = String::from("normal");
+        let mut tokens = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if let Some(tag) = line.strip_prefix('[') {
+                let (name, value) = parse_tag(tag).ok_or(format!("Invalid tag: {line}"))?;
+                match name {
+                    "Game" => {
+                        summary.game = Game::ALL.into_iter()
+                            .find(|game| game.name() == value)
+                            .ok_or(format!("Unknown game {value}"))?;
+                    },
+                    "Variant" => summary.variant = value,
+                    "Date" => date = parse_date(&value).ok_or(format!("Invalid date {value}"))?,
+                    "Time" => time = parse_time(&value).ok_or(format!("Invalid time {value}"))?,
+                    "Duration" => duration = value.parse().map_err(|_| format!("Invalid duration {value}"))?,
+                    "Crosses" => summary.players[0] = value,
+                    "Noughts" => summary.players[1] = value,
+                    "Result" => result = value,
+                    "Termination" => termination = value,
+                    "GameId" => summary.id = value.parse().unwrap_or_default(),
+                    _ => (), // ignore unknown tags
+                }
+            } else {
+                tokens.extend(line.split_whitespace().map(str::to_string));
+            }
+        }
+
+        summary.started = date.checked_add(time).ok_or("Invalid date and time")?;
+        summary.ended = summary.started.checked_add(duration).ok_or(format!("Invalid duration {duration}"))?;
+        summary.result = match (result.as_str(), termination.as_str()) {
+            ("1-0", "time forfeit") => End::Timeout(Piece::Nought),
+            ("0-1", "time forfeit") => End::Timeout(Piece::Cross),
+            ("1-0", "resignation") => End::Resignation(Piece::Nought),
+            ("0-1", "resignation") => End::Resignation(Piece::Cross),
+            ("1/2-1/2", "agreement") => End::AgreedDraw,
+            ("1-0", _) => End::Victory(Piece::Cross),
+            ("0-1", _) => End::Victory(Piece::Nought),
+            ("1/2-1/2", _) => End::Draw,
+            ("*", "aborted") => End::Aborted,
+            ("*", _) => End::Disconnect,
+            _ => return Err(format!("Invalid result {result}")),
+        };
+
+        let size = tic_tac_toe::parse_variant(&summary.variant).ok_or(format!("Invalid variant {}", summary.variant))?;
+        let mut board = Board::new(size);
+        let mut piece = Piece::Cross;
+        let mut moves = Vec::new();
+        let mut over = false;
+        for token in tokens {
+            // skip move numbers and the result at the end
+            if token.ends_with('.') || token == result {
+                continue;
+            }
+            if over {
+                return Err(format!("Move {token} after the game was over"));
+            }
+            let (x, y) = parse_coordinates(&token)
+                .filter(|(x, y)| *x < size && *y < size)
+                .ok_or(format!("Invalid move {token}"))?;
+            moves.push(board.try_place(piece.clone(), x, y)?);
+            over = board.check_victory(piece.clone()).is_some();
+            piece = piece.next();
+        }
+
+        Ok(GameRecord { summary, moves })
+    }
+}
+
+/// Coordinates as shown on the board, e.g. `B3` for x 2 y 1.
+pub fn coordinates(x: usize, y: usize) -> String {
+    format!("{}{}", (y as u8 + b'A') as char, x + 1)
+}
+
+/// Reverse of [`coordinates`], either case is accepted for the row.
+pub fn parse_coordinates(token: &str) -> Option<(usize, usize)> {
+    let mut chars = token.chars();
+    let row = chars.next()?.to_ascii_uppercase();
+    let col: usize = chars.as_str().parse().ok()?;
+    if !row.is_ascii_uppercase() || col == 0 {
+        return None;
+    }
+    Some((col - 1, (row as u8 - b'A') as usize))
+}
+
+/// Splits the inside of a tag, e.g. `Result "1-0"]`, into its name and value.
+fn parse_tag(tag: &str) -> Option<(&str, String)> {
+    let (name, rest) = tag.split_once(' ')?;
+    let value = rest.trim().strip_suffix(']')?.trim().strip_prefix('"')?.strip_suffix('"')?;
+    Some((name, value.replace("\\\"", "\"").replace("\\\\", "\\")))
+}
+
+/// Parses a `YYYY.MM.DD` date into unix seconds.
+fn parse_date(date: &str) -> Option<u64> {
+    let mut parts = date.split('.').map(|part| part.parse::<i64>().ok());
+    let (year, month, day) = (parts.next()??, parts.next()??, parts.next()??);
+    if !(0..=9999).contains(&year) || !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    u64::try_from(days_from_civil(year, month as u32, day as u32).checked_mul(86400)?).ok()
+}
+
+/// Parses a `HH:MM:SS` time into seconds since midnight.
+fn parse_time(time: &str) -> Option<u64> {
+    let mut parts = time.split(':').map(|part| part.parse::<u64>().ok());
+    let (hours, mins, secs) = (parts.next()??, parts.next()??, parts.next()??);
+    if hours >= 24 || mins >= 60 || secs >= 60 {
+        return None;
+    }
+    Some(hours * 3600 + mins * 60 + secs)
+}
+
+/// Formats a unix timestamp as a UTC date and time, e.g. `2022-10-03 14:05`.
+pub fn format_time(secs: u64) -> String {
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let mins = secs % 86400 / 60;
+    format!("{year:04}-{month:02}-{day:02} {:02}:{:02}", mins / 60, mins % 60)
+}
+
+/// Converts days since the unix epoch to a (year, month, day) date.
+/// See http://howardhinnant.github.io/date_algorithms.html#civil_from_days
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a (year, month, day) date to days since the unix epoch.
+/// The inverse of [`civil_from_days`].
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record(variant: &str, moves: &[(usize, usize)], result: End) -> GameRecord {
+        let mut piece = Piece::Cross;
+        let moves = moves.iter().map(|&(x, y)| {
+            let m = (piece.clone(), x, y);
+            piece = piece.clone().next();
+            m
+        }).collect();
+        GameRecord {
+            summary: GameSummary {
+                id: 42,
+                game: Game::TicTacToe,
+                variant: variant.to_string(),
+                players: ["alice".to_string(), "bob \"the builder\"".to_string()],
+                started: 1664805900,
+                ended: 1664806025,
+                result,
+            },
+            moves,
+        }
+    }
+
+    fn round_trip(original: &GameRecord) {
+        let imported = GameRecord::import(&original.export()).unwrap();
+        assert_eq!(imported.moves, original.moves);
+        assert_eq!(imported.summary.id, original.summary.id);
+        assert_eq!(imported.summary.variant, original.summary.variant);
+        assert_eq!(imported.summary.players, original.summary.players);
+        assert_eq!(imported.summary.started, original.summary.started);
+        assert_eq!(imported.summary.ended, original.summary.ended);
+        assert_eq!(format!("{:?}", imported.summary.result), format!("{:?}", original.summary.result));
+    }
+
+    #[test]
+    fn round_trips_every_result() {
+        let win = [(0, 0), (0, 1), (1, 0), (1, 1), (2, 0)];
+        let unfinished = [(0, 0), (1, 1)];
+        round_trip(&record("3x3", &win, End::Victory(Piece::Cross)));
+        round_trip(&record("3x3", &[(0, 0), (1, 1), (2, 2)], End::Resignation(Piece::Nought)));
+        round_trip(&record("3x3", &unfinished, End::Timeout(Piece::Cross)));
+        round_trip(&record("3x3", &unfinished, End::AgreedDraw));
+        round_trip(&record("3x3", &unfinished, End::Disconnect));
+        round_trip(&record("3x3", &unfinished, End::Aborted));
+        round_trip(&record("3x3", &[(0, 0), (1, 1), (2, 2), (0, 2), (2, 0), (1, 0), (1, 2), (2, 1), (0, 1)], End::Draw));
+    }
+
+    #[test]
+    fn round_trips_larger_boards() {
+        let moves: Vec<_> = (0..40).map(|i| (i % 9, i / 9)).filter(|(x, y)| (x + y) % 3 != 0).take(20).collect();
+        round_trip(&record("9x9", &moves, End::Disconnect));
+        round_trip(&record("4x4", &[(3, 3), (0, 0)], End::Aborted));
+    }
+
+    #[test]
+    fn rejects_invalid_variants() {
+        for variant in ["0x0", "1x1", "10x10", "100000x100000", "3x4", "3", "x3"] {
+            let text = record("3x3", &[], End::Disconnect).export().replace("\"3x3\"", &format!("\"{variant}\""));
+            assert!(GameRecord::import(&text).is_err(), "accepted variant {variant}");
+        }
+    }
+
+    #[test]
+    fn rejects_out_of_range_times() {
+        let text = record("3x3", &[], End::Disconnect).export();
+        for (tag, bad) in [
+            ("[Time \"14:05:00\"]", "[Time \"99999999999999999:00:00\"]"),
+            ("[Time \"14:05:00\"]", "[Time \"14:60:00\"]"),
+            ("[Date \"2022.10.03\"]", "[Date \"99999999999999.10.03\"]"),
+            ("[Duration \"125\"]", &format!("[Duration \"{}\"]", u64::MAX)),
+        ] {
+            assert!(text.contains(tag));
+            assert!(GameRecord::import(&text.replace(tag, bad)).is_err(), "accepted {bad}");
+        }
+    }
+
+    #[test]
+    fn rejects_illegal_moves() {
+        let won = record("3x3", &[(0, 0), (0, 1), (1, 0), (1, 1), (2, 0)], End::Victory(Piece::Cross)).export();
+        assert!(GameRecord::import(&won.replace("A3 1-0", "A3 C3 1-0")).is_err());
+        let text = record("3x3", &[(0, 0)], End::Disconnect).export();
+        assert!(GameRecord::import(&text.replace("1. A1", "1. A1 A1")).is_err());
+        assert!(GameRecord::import(&text.replace("1. A1", "1. D1")).is_err());
+        assert!(GameRecord::import(&text.replace("1. A1", "1. A4")).is_err());
+    }
+}
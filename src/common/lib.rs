@@ -1,9 +1,13 @@
+pub mod ai;
+#[cfg(feature = "native")]
+pub mod bot;
+pub mod history;
 pub mod tic_tac_toe;
 
 pub type ChannelBuf = Vec<u8>;
 
 use serde::{Serialize, Deserialize};
-use std::time::Duration;
+use std::{fmt, str::FromStr, time::Duration};
 
 /// Wait time in milliseconds.
 const THREAD_SLEEP_MS: u64 = 100;
@@ -13,10 +17,11 @@ const THREAD_SLEEP_MS: u64 = 100;
 /// resources. Value defined in ms by [`THREAD_SLEEP_MS`].
 pub const THREAD_SLEEP: Duration = Duration::from_millis(THREAD_SLEEP_MS);
 
-const PING_INTERVAL_MS: u64 = 1000;
+/// Default time between the server sending its status to every player.
+pub const PING_INTERVAL: Duration = Duration::from_millis(1000);
 
-/// Defines the ratio between the thread sleep and ping intervals.
-pub const PING_INTERVAL: u64 = PING_INTERVAL_MS / THREAD_SLEEP_MS;
+/// Longest chat message the server will accept, in characters.
+pub const MAX_CHAT_LEN: usize = 200;
 
 /// Remote IP for the client to connect to by default.
 /// 
@@ -31,10 +36,272 @@ pub const REMOTE_IP: &str = "ws.gh.maygoo.au";
 /// to connect to by default.
 pub const REMOTE_PORT: u16 = 3334;
 
+/// Every message sent over the websocket. Wraps the
+/// game specific messages alongside the server wide ones
+/// so both can share the same connection.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum Message {
+    Server(ServerMessage),
+    Client(ClientMessage),
+    TicTacToe(tic_tac_toe::Message),
+}
+
+/// Fails on bytes that aren't a message, e.g. from a client
+/// on another version or one that isn't playing fair.
+impl TryFrom<Vec<u8>> for Message {
+    type Error = bincode::Error;
+
+    fn try_from(bytes: Vec<u8>) -> Result<Self, Self::Error> {
+        bincode::deserialize(&bytes)
+    }
+}
+
+impl From<Message> for Vec<u8> {
+    fn from(message: Message) -> Self {
+        bincode::serialize(&message).unwrap()
+    }
+}
+
+impl From<ServerMessage> for Message {
+    fn from(message: ServerMessage) -> Self {
+        Message::Server(message)
+    }
+}
+
+impl From<ClientMessage> for Message {
+    fn from(message: ClientMessage) -> Self {
+        Message::Client(message)
+    }
+}
+
+impl From<tic_tac_toe::Message> for Message {
+    fn from(message: tic_tac_toe::Message) -> Self {
+        Message::TicTacToe(message)
+    }
+}
+
+/// Time limits for each player in a game.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimeControl {
+    /// Every move has to be made within the limit.
+    PerMove(Duration),
+    /// Each player has a total time for the game, with
+    /// the increment added after every move they make.
+    Fischer { initial: Duration, increment: Duration },
+}
+
+impl TimeControl {
+    /// Time each player starts the game with.
+    pub fn initial(&self) -> Duration {
+        match self {
+            TimeControl::PerMove(limit) => *limit,
+            TimeControl::Fischer { initial, .. } => *initial,
+        }
+    }
+}
+
+/// Written as `30s` for 30 seconds per move, or `5+3` for
+/// 5 minutes each with 3 seconds added after every move.
+impl fmt::Display for TimeControl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TimeControl::PerMove(limit) => write!(f, "{}s", limit.as_secs()),
+            TimeControl::Fischer { initial, increment } => write!(f, "{}+{}", initial.as_secs() / 60, increment.as_secs()),
+        }
+    }
+}
+
+impl FromStr for TimeControl {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || format!("Invalid time control {s}, expected e.g. 30s or 5+3");
+        let secs = |n: &str| n.trim().parse::<u64>().map_err(|_| error());
+
+        let control = if let Some(limit) = s.strip_suffix('s') {
+            TimeControl::PerMove(Duration::from_secs(secs(limit)?))
+        } else if let Some((initial, increment)) = s.split_once('+') {
+            TimeControl::Fischer {
+                initial: Duration::from_secs(secs(initial)? * 60),
+                increment: Duration::from_secs(secs(increment)?),
+            }
+        } else {
+            return Err(error());
+        };
+
+        if control.initial().is_zero() {
+            Err(error())
+        } else {
+            Ok(control)
+        }
+    }
+}
+
+/// Formats the time left on a clock as `m:ss`.
+pub fn format_clock(remaining: Duration) -> String {
+    let secs = remaining.as_secs();
+    format!("{}:{:02}", secs / 60, secs % 60)
+}
+
+/// Games that can be played on the server.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Game {
+    TicTacToe,
+}
+
+impl Game {
+    pub const ALL: [Game; 1] = [Game::TicTacToe];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Game::TicTacToe => tic_tac_toe::NAME,
+        }
+    }
+
+    /// Short name used on the command line, e.g. `tictactoe`.
+    pub fn id(&self) -> &'static str {
+        match self {
+            Game::TicTacToe => "tictactoe",
+        }
+    }
+}
+
+/// Accepts the id or the name in any case, e.g. `tictactoe` or `Tic Tac Toe`.
+impl FromStr for Game {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let id: String = s.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+        Game::ALL
+            .into_iter()
+            .find(|game| game.id() == id)
+            .ok_or_else(|| {
+                let ids: Vec<_> = Game::ALL.iter().map(Game::id).collect();
+                format!("Invalid game {s}, expected one of {}", ids.join(", "))
+            })
+    }
+}
+
 /// Server messages, indiscriminate of the selected game.
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum ServerMessage {
     Ping(ServerStatus),
+    /// Sent after a successful sign up, login or resume.
+    /// The token can be used to resume the session later
+    /// without sending the password again.
+    LoggedIn { username: String, token: String },
+    LoggedOut,
+    AccountError(String),
+    /// Generic error, e.g. a request that was refused.
+    Error(String),
+    /// Highest rated accounts for a game, best first.
+    Leaderboard(Game, Vec<Ranking>),
+    /// Sent to each account after a rated game.
+    RatingChange { game: Game, rating: u32, change: i32 },
+    /// Most recent finished games, newest first.
+    History(Vec<history::GameSummary>),
+    GameRecord(history::GameRecord),
+    /// Games currently being played that can be spectated.
+    LiveGames(Vec<LiveGame>),
+    Chat(ChatMessage),
+    /// The server is shutting down. Games in progress have this long
+    /// to finish, after which they are saved to carry on after the
+    /// restart, or aborted if a guest is playing, and everyone is
+    /// disconnected. Sent again every so often as a countdown.
+    Shutdown(Duration),
+    /// Answers [`ClientMessage::StopSpectating`], nothing more
+    /// from the game is sent after it.
+    StoppedSpectating,
+    /// The game in progress was saved as the server shut down, it
+    /// carries on once both players log back in after the restart.
+    Suspended(Game),
+    /// A game interrupted by the server restarting will carry on
+    /// once the opponent with this name logs back in.
+    Resuming(Game, String),
+}
+
+/// Client messages handled by the server outside of any game.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum ClientMessage {
+    Account(AccountRequest),
+    Leaderboard(Game),
+    /// Join the matchmaking queue. Players aren't matched
+    /// until they send this, e.g. after logging in.
+    FindGame(Game),
+    /// List finished games, only those played
+    /// by the given username if there is one.
+    History(Option<String>),
+    /// Request the full record of a finished game by its id.
+    GameRecord(i64),
+    LiveGames,
+    /// Watch a live game by its id. Spectators are sent the
+    /// board so far followed by every move until the game ends.
+    Spectate(u64),
+    StopSpectating,
+    /// Send a chat message, at most [`MAX_CHAT_LEN`] characters.
+    Chat(ChatChannel, String),
+    /// Start a game against a computer opponent straight away
+    /// instead of waiting for another player.
+    PlayBot(Game, ai::Difficulty),
+    /// Give up a game restored after a restart instead of waiting for the
+    /// opponent to come back. It is recorded as aborted and both players
+    /// go back to the matchmaking queue.
+    AbandonGame,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub enum AccountRequest {
+    SignUp(Credentials),
+    Login(Credentials),
+    /// Resume a previous login with its session token.
+    Resume(String),
+    /// Flag the logged in account as a bot, for accounts played by a
+    /// program. The flag is permanent and shown next to the username.
+    FlagBot,
+    Logout,
+}
+
+/// Username and password pair for registered accounts.
+/// Players who never send these play as guests.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+/// A single row of the leaderboard.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Ranking {
+    pub username: String,
+    pub rating: u32,
+    pub games: u32,
+}
+
+/// A game in progress, as listed to possible spectators.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LiveGame {
+    pub id: u64,
+    pub game: Game,
+    /// Names of the first and second player.
+    pub players: [String; 2],
+    pub spectators: usize,
+}
+
+/// Who receives a chat message.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChatChannel {
+    /// Everyone connected to the server.
+    Lobby,
+    /// The players and spectators of the game
+    /// the sender is playing or watching.
+    Game,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ChatMessage {
+    pub channel: ChatChannel,
+    pub from: String,
+    pub text: String,
 }
 
 /// Server status sent to each client.
@@ -1,36 +1,81 @@
 use serde::{Serialize, Deserialize};
+use std::time::Duration;
+
+use crate::TimeControl;
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum Message {
     Preamble(ClientState),
-    WaitTurn,
-    YourTurn,
-    Move((Piece, usize, usize)),
+    /// Sent at the start of every turn, with the time left
+    /// on both clocks if the game has a time control.
+    /// Spectators are sent the wait message too.
+    WaitTurn(Option<Clocks>),
+    YourTurn(Option<Clocks>),
+    Move(Move),
     InvalidMove(String),
+    /// Give up the game, can be sent at any time.
+    Resign,
+    /// Offer sent to the opponent, who answers with [`Message::Answer`].
+    /// Offers are dropped once either player makes a move.
+    Offer(Offer),
+    Answer(Offer, bool),
+    /// Moves removed from the board after an accepted takeback,
+    /// the most recent first.
+    TakeBack(Vec<Move>),
+    /// Sent to spectators when they start watching.
+    Spectate(SpectatorState),
+    /// Number of spectators, sent to everyone in
+    /// the game whenever it changes.
+    Spectators(usize),
     // Nought or Cross piece means they win
     // Empty piece means game is over i.e. disconnect
     GameOver(End),
-    // temporary, move to standard server message
-    Status(super::ServerStatus),
-}
-
-impl From<Vec<u8>> for Message {
-    fn from(bytes: Vec<u8>) -> Self {
-        bincode::deserialize(&bytes).unwrap()
-    }
 }
 
-impl From<Message> for Vec<u8> {
-    fn from(message: Message) -> Self {
-        bincode::serialize(&message).unwrap()
-    }
-}
+/// A piece placed at the x, y coordinates.
+pub type Move = (Piece, usize, usize);
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub enum End {
     Victory(Piece),
     Draw,
     Disconnect,
+    /// The player with this piece ran out of time and lost.
+    Timeout(Piece),
+    /// The player with this piece resigned.
+    Resignation(Piece),
+    AgreedDraw,
+    /// Stopped by the server before it could finish, e.g. when
+    /// shutting down. Nobody wins and the game isn't rated.
+    Aborted,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Offer {
+    Draw,
+    /// Undo the last move made by the player asking,
+    /// along with any move their opponent made since.
+    Takeback,
+    /// Play again with colours swapped, only once the game is over. If it
+    /// isn't accepted in time both players are sent a declined answer
+    /// and go back to the lobby.
+    Rematch,
+}
+
+/// Time left for each player at the start of a turn.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct Clocks {
+    pub crosses: Duration,
+    pub noughts: Duration,
+}
+
+impl Clocks {
+    pub fn get(&self, piece: &Piece) -> Duration {
+        match piece {
+            Piece::Nought => self.noughts,
+            _ => self.crosses,
+        }
+    }
 }
 
 #[derive(Clone, PartialEq, Eq, Serialize, Deserialize, Debug)]
@@ -65,6 +110,24 @@ pub struct ClientState {
     pub turn: Turn,
     pub opponent: String,
     pub piece: Piece,
+    /// Ratings are only set for registered accounts.
+    pub rating: Option<u32>,
+    pub opponent_rating: Option<u32>,
+    pub time_control: Option<TimeControl>,
+    /// Offer from the opponent waiting for an answer, only used by the clients.
+    pub offer: Option<Offer>,
+}
+
+/// The game so far as seen by a spectator.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct SpectatorState {
+    pub board: Board,
+    /// Names of the crosses and noughts players.
+    pub players: [String; 2],
+    pub ratings: [Option<u32>; 2],
+    pub current_player: Piece,
+    pub spectators: usize,
+    pub clocks: Option<Clocks>,
 }
 
 #[derive(PartialEq, Eq, Serialize, Deserialize, Debug, Clone)]
@@ -83,6 +146,9 @@ pub struct Board {
 
 pub const NAME: &str = "Tic Tac Toe";
 pub const BOARD_SIZE: usize = 3;
+/// Board sizes that can be played, larger boards
+/// run out of coordinates in the move notation.
+pub const BOARD_SIZES: std::ops::RangeInclusive<usize> = 3..=9;
 pub const INSTRUCTIONS: &str = "
   Wait until your turn then
   enter two numbers, eg 1 2
@@ -90,6 +156,19 @@ pub const INSTRUCTIONS: &str = "
   at the the top left cell.
 ";
 
+/// Name of the variant played on a board of the given size, e.g. `3x3`.
+pub fn variant(board_size: usize) -> String {
+    format!("{board_size}x{board_size}")
+}
+
+/// Board size of a variant created by [`variant`],
+/// if it is one of the [`BOARD_SIZES`] that can be played.
+pub fn parse_variant(variant: &str) -> Option<usize> {
+    variant.split_once('x')
+        .and_then(|(size, _)| size.parse().ok())
+        .filter(|size| BOARD_SIZES.contains(size) && self::variant(*size) == variant)
+}
+
 impl Board {
     pub fn new(size: usize) -> Self {
         Board {
@@ -105,7 +184,11 @@ impl Board {
         self.grid[y][x] = p;
     }
 
-    pub fn try_place(&mut self, p: Piece, x: usize, y:usize) -> Result<(Piece, usize, usize), String> {
+    pub fn try_place(&mut self, p: Piece, x: usize, y:usize) -> Result<Move, String> {
+        // moves come from clients so may be off the board
+        if x >= self.size || y >= self.size {
+            return Err(format!("That cell isn't on the {} board! Enter another move", variant(self.size)));
+        }
         // check if cell is empty then do move
         match &mut self.grid[y][x] {
             Piece::Empty => {
@@ -162,8 +245,24 @@ impl Board {
 
     fn check_draw(&self) -> bool {
         !self.grid.iter()
-            .map(|row| row.contains(&Piece::Empty))
-            .any(|p| p)
+            .any(|row| row.contains(&Piece::Empty))
+    }
+
+    pub fn get(&self, x: usize, y: usize) -> &Piece {
+        &self.grid[y][x]
+    }
+
+    /// Coordinates of every cell without a piece, row by row.
+    pub fn empty_cells(&self) -> Vec<(usize, usize)> {
+        let mut cells = Vec::new();
+        for (y, row) in self.grid.iter().enumerate() {
+            for (x, cell) in row.iter().enumerate() {
+                if *cell == Piece::Empty {
+                    cells.push((x, y));
+                }
+            }
+        }
+        cells
     }
 
     pub fn iter(&self) -> std::slice::Iter<'_, Vec<Piece>> {
@@ -216,6 +315,10 @@ impl ClientState {
             turn: Turn::Begin,
             opponent,
             piece,
+            rating: None,
+            opponent_rating: None,
+            time_control: None,
+            offer: None,
         }
     }
 }
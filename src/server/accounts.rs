@@ -0,0 +1,205 @@
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    ops::RangeInclusive,
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use argon2::{
+    password_hash::{rand_core::{OsRng, RngCore}, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+
+use common::{AccountRequest, Credentials, Game};
+
+use crate::database::{self, Database};
+use crate::error::lock;
+use crate::ratings::{self, Rating};
+
+/// Sessions older than this need to log in again.
+const SESSION_LIFETIME_SECS: u64 = 30 * 24 * 60 * 60;
+const USERNAME_LEN: RangeInclusive<usize> = 3..=20;
+const MIN_PASSWORD_LEN: usize = 8;
+
+/// Each connection and each username can fail to log in at most
+/// `LOGIN_ATTEMPTS` times within every `LOGIN_WINDOW`, so passwords
+/// can't be guessed quickly.
+const LOGIN_ATTEMPTS: usize = 5;
+const LOGIN_WINDOW: Duration = Duration::from_secs(60);
+
+/// Recent failed logins for each username, from any connection.
+static FAILED_LOGINS: Mutex<BTreeMap<String, VecDeque<Instant>>> = Mutex::new(BTreeMap::new());
+
+/// A registered account. Players without one are guests.
+#[derive(Clone, Debug)]
+pub struct Account {
+    pub id: i64,
+    pub username: String,
+    /// Flagged as played by a program rather than a person.
+    pub bot: bool,
+    /// Cached ratings, loaded on login and kept
+    /// up to date after each rated game.
+    pub ratings: HashMap<Game, Rating>,
+}
+
+impl Account {
+    pub fn rating(&self, game: Game) -> Rating {
+        self.ratings.get(&game).copied().unwrap_or_default()
+    }
+}
+
+/// Result of a successful account request.
+pub enum Change {
+    /// Logged into the account with the session token.
+    LoggedIn(Account, String),
+    LoggedOut,
+}
+
+/// Tracks the failed logins of a single connection.
+pub struct Attempts {
+    failed: VecDeque<Instant>,
+}
+
+impl Attempts {
+    pub fn new() -> Self {
+        Attempts {
+            failed: VecDeque::new(),
+        }
+    }
+
+    /// Fails if the connection or the username has
+    /// failed to log in too many times recently.
+    fn check(&mut self, username: &str) -> Result<(), String> {
+        let now = Instant::now();
+        expire(&mut self.failed, now);
+        let mut by_username = lock(&FAILED_LOGINS);
+        // forget usernames once their window has passed
+        by_username.retain(|_, failed| {
+            expire(failed, now);
+            !failed.is_empty()
+        });
+
+        if self.failed.len() >= LOGIN_ATTEMPTS || by_username.get(username).is_some_and(|failed| failed.len() >= LOGIN_ATTEMPTS) {
+            Err("Too many failed logins, try again later".to_string())
+        } else {
+            Ok(())
+        }
+    }
+
+    fn fail(&mut self, username: &str) {
+        let now = Instant::now();
+        self.failed.push_back(now);
+        lock(&FAILED_LOGINS).entry(username.to_string()).or_default().push_back(now);
+    }
+}
+
+fn expire(failed: &mut VecDeque<Instant>, now: Instant) {
+    while failed.front().is_some_and(|at| now.duration_since(*at) > LOGIN_WINDOW) {
+        failed.pop_front();
+    }
+}
+
+/// Handles the account requests from a single connection.
+/// `token` is the connection's current session token, if any.
+/// Errors are returned as messages that can be shown to the player.
+pub fn handle(db: &Database, msg: AccountRequest, token: Option<&str>, attempts: &mut Attempts) -> Result<Change, String> {
+    match msg {
+        AccountRequest::SignUp(Credentials { username, password }) => {
+            validate(&username, &password)?;
+            let hash = hash_password(&password)?;
+            match db.create_account(&username, &hash).map_err(db_error)? {
+                Some(account) => login(db, account),
+                None => Err(format!("The username {username} is already taken")),
+            }
+        },
+        AccountRequest::Login(Credentials { username, password }) => {
+            attempts.check(&username)?;
+            match db.find_account(&username).map_err(db_error)? {
+                Some((account, hash)) if verify_password(&password, &hash) => login(db, account),
+                _ => {
+                    attempts.fail(&username);
+                    Err("Invalid username or password".to_string())
+                },
+            }
+        },
+        AccountRequest::Resume(token) => {
+            let since = database::now().saturating_sub(SESSION_LIFETIME_SECS);
+            match db.find_session(&token, since).map_err(db_error)? {
+                Some(mut account) => {
+                    ratings::load(db, &mut account).map_err(db_error)?;
+                    Ok(Change::LoggedIn(account, token))
+                },
+                None => Err("Session has expired, please log in again".to_string()),
+            }
+        },
+        AccountRequest::FlagBot => {
+            let token = token.ok_or("Log in before flagging the account as a bot")?;
+            let since = database::now().saturating_sub(SESSION_LIFETIME_SECS);
+            match db.find_session(token, since).map_err(db_error)? {
+                Some(mut account) => {
+                    db.flag_bot(&account).map_err(db_error)?;
+                    account.bot = true;
+                    ratings::load(db, &mut account).map_err(db_error)?;
+                    Ok(Change::LoggedIn(account, token.to_string()))
+                },
+                None => Err("Session has expired, please log in again".to_string()),
+            }
+        },
+        AccountRequest::Logout => {
+            if let Some(token) = token {
+                db.delete_session(token).map_err(db_error)?;
+            }
+            Ok(Change::LoggedOut)
+        },
+    }
+}
+
+fn login(db: &Database, mut account: Account) -> Result<Change, String> {
+    ratings::load(db, &mut account).map_err(db_error)?;
+    let token = new_token();
+    db.create_session(&account, &token).map_err(db_error)?;
+    Ok(Change::LoggedIn(account, token))
+}
+
+fn validate(username: &str, password: &str) -> Result<(), String> {
+    if !USERNAME_LEN.contains(&username.chars().count()) {
+        Err(format!("Usernames must be between {} and {} characters", USERNAME_LEN.start(), USERNAME_LEN.end()))
+    } else if !username.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Err("Usernames can only contain letters, numbers and underscores".to_string())
+    } else if password.chars().count() < MIN_PASSWORD_LEN {
+        Err(format!("Passwords must be at least {MIN_PASSWORD_LEN} characters"))
+    } else {
+        Ok(())
+    }
+}
+
+/// Hashes the password with a random salt. The salt and
+/// parameters are stored as part of the returned string.
+fn hash_password(password: &str) -> Result<String, String> {
+    let salt = SaltString::generate(&mut OsRng);
+    Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .map(|hash| hash.to_string())
+        .map_err(|e| {
+            log::error!("Unable to hash password. {e}");
+            "Unable to create account".to_string()
+        })
+}
+
+fn verify_password(password: &str, hash: &str) -> bool {
+    PasswordHash::new(hash)
+        .map(|hash| Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+        .unwrap_or(false)
+}
+
+/// Random 256 bit session token, hex encoded.
+fn new_token() -> String {
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
+
+pub fn db_error(e: rusqlite::Error) -> String {
+    log::error!("Database error. {e}");
+    "Server error, please try again later".to_string()
+}
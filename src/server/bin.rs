@@ -1,124 +1,425 @@
 use std::{
-    env,
+    process,
     thread,
-    sync::mpsc::channel,
-    net::{TcpListener, SocketAddr, TcpStream},
-    fs::File, io::Read,
+    sync::mpsc::{channel, Receiver, Sender},
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, TcpListener, SocketAddr, TcpStream},
+    io::{self, Read, Write},
+    sync::{atomic::{AtomicBool, Ordering}, Arc},
+    time::{Duration, Instant},
 };
-use native_tls::{Identity, TlsAcceptor, TlsStream};
+use native_tls::TlsStream;
+use signal_hook::{consts::TERM_SIGNALS, iterator::Signals};
+use tungstenite::WebSocket;
+use tungstenite::protocol::{frame::coding::CloseCode, CloseFrame};
 
-use common::ChannelBuf;
+use common::{AccountRequest, ChannelBuf, ClientMessage, ServerMessage};
 
+mod accounts;
+mod chat;
+mod config;
+mod database;
+mod error;
 mod games;
+mod history;
+mod ratings;
+mod tls;
+
+use database::Database;
+use error::Error;
+
+/// How long a client has for the TLS and websocket handshakes.
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
 
 /// Starts the board game server.
 /// 
 /// Default port is specified in [`common`](common::REMOTE_PORT)
 /// but can be changed by passing in a cli argument
-/// when running the server.
+/// when running the server. Registered accounts are
+/// stored in a local SQLite database. Settings can also be
+/// read from a TOML file with `--config`, see `--help`
+/// and `server.example.toml`.
 /// 
-/// The server requires a valid `pkcs #12` keystore. To
-/// generate one for local testing you can use the following
-/// commands:
+/// Unless `--plain` is passed the server requires a valid
+/// `pkcs #12` keystore, or a PEM certificate and key passed
+/// with `--cert` and `--key`. To generate them for local
+/// testing you can use the following commands:
 /// ```bash
 /// openssl req -new -newkey rsa:4096 -x509 -nodes -out cert.crt -keyout key.pem
 /// openssl pkcs12 -export -out keystore.pkcs -inkey key.pem -in cert.crt
 /// ```
+/// Either is reloaded when the files change or on `SIGHUP`.
+///
+/// On `SIGINT` or `SIGTERM` the server stops accepting connections
+/// and gives games in progress time to finish before closing every
+/// connection. A second signal exits straight away.
 fn main() {
-    // initialise server with default binding 0.0.0.0:3334
-    const DEFAULT_IP: [u8; 4] = [0,0,0,0];
-    // check command line args for port
-    let port: u16 = env::args().collect::<Vec<String>>().get(1).and_then(|a| a.parse().ok()).unwrap_or(common::REMOTE_PORT);
-    let addr = SocketAddr::from((DEFAULT_IP, port));
+    let settings = config::Settings::load();
+    env_logger::Builder::new()
+        .filter_level(settings.log)
+        .parse_default_env()
+        .init();
+
+    let db = match Database::open(&settings.database) {
+        Ok(db) => db,
+        Err(e) => fail(format!("Unable to open database {}. {e}", settings.database.display())),
+    };
+
+    let term = Arc::new(AtomicBool::new(false));
+    for signal in TERM_SIGNALS {
+        // exits if the flag is already set, i.e. on the second signal
+        if let Err(e) = signal_hook::flag::register_conditional_shutdown(*signal, 1, Arc::clone(&term)) {
+            log::warn!("Unable to exit on a second signal {signal}. {e}");
+        }
+    }
 
     // create shared vector for list of active connections
-    let lobby = games::Lobby::new();
+    let tick = settings.lobby.tick;
+    let heartbeat = settings.heartbeat;
+    let lobby = games::Lobby::new(db.clone(), settings.lobby);
+    // carry on games that were in progress when the server last stopped
+    lobby.restore();
+
     // spawn thread to monitor connections, removing finished threads
     lobby.monitor();
     // start game
     lobby.begin_game();
 
-    let mut file = File::open("tls/keystore.pkcs").expect("Needs keys");
-    let mut identity = vec![];
-    file.read_to_end(&mut identity).unwrap();
-    let identity = Identity::from_pkcs12(&identity, "").unwrap();
-
-    match TcpListener::bind(addr) {
-        Ok(listener) => {
-            println!("Server listening on {}", listener.local_addr().unwrap());
-            println!("promoting to tls");
-
-            let acceptor = TlsAcceptor::new(identity).unwrap();
-            //let acceptor = Arc::new(acceptor);
-
-            for stream in listener.incoming() {
-                match stream {
-                    Ok(stream) => {
-                        match acceptor.accept(stream) {
-                            Ok(stream) => handle_connection(stream, &lobby),
-                            Err(e) => eprintln!("Incoming connection not using ssl. {e}")
-                        };
-                    },
-                    Err(e) => eprintln!("Unable to connect. {e}"),
-                }
+    let acceptor = settings.keys.map(|keys| match tls::Tls::new(keys) {
+        Ok(tls) => {
+            tls.watch();
+            tls
+        },
+        Err(e) => fail(format!("Needs keys, or run with --plain. {e}")),
+    });
+
+    let addr = settings.address;
+    let listener = match TcpListener::bind(addr) {
+        Ok(listener) => listener,
+        Err(e) => fail(format!("Unable to bind to {addr}. {e}")),
+    };
+    let local = listener.local_addr().unwrap_or(addr);
+    log::info!("Server listening on {local}");
+    match acceptor {
+        Some(_) => log::info!("promoting to tls"),
+        None => log::info!("serving plain websockets"),
+    }
+    stop_on_signal(Arc::clone(&term), local);
+
+    for stream in listener.incoming() {
+        if term.load(Ordering::Relaxed) {
+            break;
+        }
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(e) => {
+                log::warn!("Unable to connect. {e}");
+                // e.g. out of file descriptors, give connections time to close
+                thread::sleep(tick);
+                continue;
+            },
+        };
+
+        // handshakes run on the connection's own thread
+        // so a slow client can't hold up anyone else
+        let (lobby, db, acceptor) = (lobby.clone(), db.clone(), acceptor.clone());
+        thread::spawn(move|| {
+            let timeouts = stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))
+                .and_then(|()| stream.set_write_timeout(Some(HANDSHAKE_TIMEOUT)));
+            if let Err(e) = timeouts {
+                log::warn!("Unable to connect. {e}");
+                return;
+            }
+            match &acceptor {
+                Some(acceptor) => match acceptor.accept(stream) {
+                    Ok(stream) => handle_connection(stream, &lobby, &db, heartbeat),
+                    Err(e) => log::warn!("Incoming connection not using ssl. {e}")
+                },
+                None => handle_connection(stream, &lobby, &db, heartbeat),
             }
+        });
+    }
+
+    lobby.close();
+    log::info!("Server stopped");
+}
+
+/// Sets `term` on the first `SIGINT` or `SIGTERM`, then connects to
+/// the server at `addr` to wake the accept loop so it sees the flag.
+fn stop_on_signal(term: Arc<AtomicBool>, addr: SocketAddr) {
+    let mut signals = match Signals::new(TERM_SIGNALS) {
+        Ok(signals) => signals,
+        Err(e) => {
+            log::warn!("Unable to shut down gracefully on signals. {e}");
+            return;
         },
-        Err(e) => panic!("Unable to bind to {addr}. {e}"),
     };
+    thread::spawn(move|| {
+        if signals.forever().next().is_some() {
+            term.store(true, Ordering::Relaxed);
+            let ip = match addr.ip() {
+                IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+                IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+                ip => ip,
+            };
+            if let Err(e) = TcpStream::connect((ip, addr.port())) {
+                log::warn!("Unable to stop accepting connections. {e}");
+            }
+        }
+    });
 }
 
-fn handle_connection(stream: TlsStream<TcpStream>, lobby: &games::Lobby) {
+/// Exits when the server can't start.
+fn fail(msg: String) -> ! {
+    log::error!("{msg}");
+    process::exit(1)
+}
+
+/// Connections the server accepts, with or without TLS.
+trait Stream: Read + Write + Send + 'static {
+    /// The underlying connection.
+    fn tcp(&self) -> &TcpStream;
+}
+
+impl Stream for TcpStream {
+    fn tcp(&self) -> &TcpStream {
+        self
+    }
+}
+
+impl Stream for TlsStream<TcpStream> {
+    fn tcp(&self) -> &TcpStream {
+        self.get_ref()
+    }
+}
+
+fn handle_connection(stream: impl Stream, lobby: &games::Lobby, db: &Database, heartbeat: config::Heartbeat) {
     // convert stream to websocket
-    let client = stream.get_ref().peer_addr().unwrap();
+    let client = match stream.tcp().peer_addr() {
+        Ok(client) => client,
+        Err(e) => {
+            log::warn!("Unable to connect. {e}");
+            return;
+        },
+    };
     let mut websocket = match tungstenite::accept(stream) {
         Ok(ws) => {
-            println!("Connected to {client}");
+            log::info!("Connected to {client}");
             ws
         },
         Err(e) => {
-            eprintln!("Error creating websocket for {client}. {e}");
+            log::warn!("Error creating websocket for {client}. {e}");
             return;
         }
     };
 
-    // set inner tcpstream to nonblocking mode
-    // need to do this after the websocket handshake
-    websocket.get_mut().get_mut().set_nonblocking(true).unwrap();
+    if lobby.is_full() {
+        log::warn!("Turned away {client}, the server is full");
+        let msg: ChannelBuf = common::Message::from(ServerMessage::Error("The server is full, try again later".to_string())).into();
+        let _ = websocket.write_message(tungstenite::Message::binary(msg));
+        // send the close without waiting for the client to acknowledge
+        // it, a client that never answers would keep the thread around
+        let _ = websocket.close(None);
+        let _ = websocket.write_pending();
+        return;
+    }
+
+    // wait up to a tick for the client before checking for messages from
+    // the game, so idle connections sleep rather than spin. Writes give
+    // up on clients that stop reading once they would have timed out
+    let tcp = websocket.get_ref().tcp();
+    let timeouts = tcp.set_read_timeout(Some(lobby.settings().tick))
+        .and_then(|()| tcp.set_write_timeout(Some(heartbeat.timeout)));
+    if let Err(e) = timeouts {
+        log::warn!("Unable to connect to {client}. {e}");
+        return;
+    }
 
     // create channel pair for duplex communication
     let (tx_t, rx) = channel::<ChannelBuf>();
     let (tx, rx_t) = channel::<ChannelBuf>();
 
-    let t = thread::spawn(move|| {     
-        loop {
-            match websocket.read_message() {
-                Ok(msg) if msg.is_binary() => {
+    let lobby_t = lobby.clone();
+    let db = db.clone();
+    // dropped once the player is in the lobby, so their
+    // first message can't arrive before they are
+    let (joined, wait_join) = channel::<()>();
+    let t = thread::spawn(move|| {
+        let _ = wait_join.recv();
+        let channels = (tx_t, rx_t);
+        match serve(&mut websocket, client, &lobby_t, &db, heartbeat, channels) {
+            Ok(()) => log::debug!("Closed the connection to {client}"),
+            Err(e) => log::warn!("Lost the connection to {client}. {e}"),
+        }
+    });
+
+    lobby.add_and_print_connections(games::Player::new(t, client, tx, rx));
+    drop(joined);
+}
+
+/// Passes messages between the client and the server until the connection
+/// closes. Failures end only this connection, which the lobby and the
+/// player's game then treat as them disconnecting.
+fn serve(
+    websocket: &mut WebSocket<impl Stream>,
+    client: SocketAddr,
+    lobby: &games::Lobby,
+    db: &Database,
+    heartbeat: config::Heartbeat,
+    (to_game, from_game): (Sender<ChannelBuf>, Receiver<ChannelBuf>),
+) -> Result<(), Error> {
+    // session token if the player has logged in
+    let mut token: Option<String> = None;
+    let mut chat = chat::Limiter::new();
+    let mut attempts = accounts::Attempts::new();
+    // set once the close frame has been sent
+    let mut closing = false;
+    // any message counts, including pongs and the client's own pings
+    let mut last_seen = Instant::now();
+    let mut pinged = Instant::now();
+    loop {
+        let read = websocket.read_message();
+        if read.is_ok() {
+            last_seen = Instant::now();
+        }
+        match read {
+            Ok(msg) if msg.is_binary() => {
+                let data = msg.into_data();
+                match bincode::deserialize::<common::Message>(&data) {
+                    Ok(common::Message::Client(msg)) => {
+                        if let Some(reply) = handle_client(msg, &mut token, (&mut chat, &mut attempts), client, lobby, db) {
+                            let reply: ChannelBuf = common::Message::from(reply).into();
+                            websocket.write_message(tungstenite::Message::binary(reply))?;
+                        }
+                    },
                     // send the data to through channel to the game controller
-                    tx_t.send(msg.into_data()).unwrap();
-                },
-                Ok(msg) => {
-                    if msg.is_text() {
-                        // not expecting text messages
-                        // print them out
-                        println!("Text msg received from {client}: {:?}", msg.to_text());
-                    } else if msg.is_close() {
-                        break;
-                    }
+                    Ok(common::Message::TicTacToe(_)) => to_game.send(data).map_err(|_| Error::Disconnected(client))?,
+                    Ok(msg) => log::warn!("Unexpected msg received from {client}: {msg:?}"),
+                    Err(e) => log::warn!("Invalid msg received from {client}. {e}"),
+                }
+            },
+            Ok(msg) => {
+                if msg.is_text() {
+                    // not expecting text messages
+                    // print them out
+                    log::debug!("Text msg received from {client}: {:?}", msg.to_text());
+                } else if msg.is_close() {
+                    return Ok(());
+                }
+            },
+            // nothing arrived within the tick, `TimedOut` on windows
+            Err(tungstenite::Error::Io(e)) if matches!(e.kind(), io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut) => (),
+            // the client acknowledged the close frame
+            Err(tungstenite::Error::ConnectionClosed) => return Ok(()),
+            Err(e) => return Err(e.into()),
+        }
+
+        // only the acknowledgement is left to wait for, which the lobby
+        // gives up on, no more pings or messages are written after the close
+        if closing {
+            continue;
+        }
+
+        // the client may have lost its network without closing
+        // the connection, in which case nothing arrives
+        if last_seen.elapsed() >= heartbeat.timeout {
+            log::warn!("{client} timed out, nothing heard for {}s", last_seen.elapsed().as_secs());
+            return Ok(());
+        }
+        if last_seen.elapsed() >= heartbeat.interval && pinged.elapsed() >= heartbeat.interval {
+            pinged = Instant::now();
+            // the pong is sent back automatically and counts as hearing from the client
+            websocket.write_message(tungstenite::Message::Ping(Vec::new()))?;
+        }
+
+        // receive data through channel from game controller
+        while let Ok(send) = from_game.try_recv() {
+            websocket.write_message(tungstenite::Message::binary(send))?;
+        }
+        if lobby.shutdown().closed() {
+            closing = true;
+            websocket.close(Some(CloseFrame {
+                code: CloseCode::Away,
+                reason: "The server is shutting down".into(),
+            }))?;
+        }
+    }
+}
+
+/// Handles the messages sent by the player at `client`
+/// that aren't part of a game, returning the reply if any.
+fn handle_client(
+    msg: ClientMessage,
+    token: &mut Option<String>,
+    (chat, attempts): (&mut chat::Limiter, &mut accounts::Attempts),
+    client: SocketAddr,
+    lobby: &games::Lobby,
+    db: &Database,
+) -> Option<ServerMessage> {
+    match msg {
+        ClientMessage::Account(request) => Some(handle_account(request, token, attempts, client, lobby, db)),
+        ClientMessage::Leaderboard(game) => match ratings::leaderboard(db, game) {
+            Ok(rankings) => Some(ServerMessage::Leaderboard(game, rankings)),
+            Err(e) => Some(ServerMessage::Error(accounts::db_error(e))),
+        },
+        ClientMessage::FindGame(game) => lobby.find_game(client, game).err().map(ServerMessage::Error),
+        ClientMessage::PlayBot(game, difficulty) => lobby.play_bot(client, game, difficulty).err().map(ServerMessage::Error),
+        ClientMessage::AbandonGame => lobby.abandon(client).err().map(ServerMessage::Error),
+        ClientMessage::History(username) => match db.games(username.as_deref(), history::HISTORY_SIZE) {
+            Ok(games) => Some(ServerMessage::History(games)),
+            Err(e) => Some(ServerMessage::Error(accounts::db_error(e))),
+        },
+        ClientMessage::GameRecord(id) => match db.game_record(id) {
+            Ok(Some(record)) => Some(ServerMessage::GameRecord(record)),
+            Ok(None) => Some(ServerMessage::Error(format!("No game with id {id}"))),
+            Err(e) => Some(ServerMessage::Error(accounts::db_error(e))),
+        },
+        ClientMessage::LiveGames => Some(ServerMessage::LiveGames(lobby.live_games())),
+        ClientMessage::Spectate(id) => lobby.spectate(client, Some(id)).err().map(ServerMessage::Error),
+        ClientMessage::StopSpectating => lobby.spectate(client, None).err().map(ServerMessage::Error),
+        ClientMessage::Chat(channel, text) => chat.check()
+            .and_then(|()| chat::clean(&text))
+            .and_then(|text| lobby.chat(client, channel, text))
+            .err()
+            .map(ServerMessage::Error),
+    }
+}
+
+/// Applies an account request from the player at `client`,
+/// keeping their lobby entry in sync with the session token.
+fn handle_account(msg: AccountRequest, token: &mut Option<String>, attempts: &mut accounts::Attempts, client: SocketAddr, lobby: &games::Lobby, db: &Database) -> ServerMessage {
+    // refuse before the database is changed, e.g. during a game
+    if let Err(e) = lobby.can_set_account(client) {
+        return ServerMessage::AccountError(e);
+    }
+    let resumed = matches!(msg, AccountRequest::Resume(_));
+
+    match accounts::handle(db, msg, token.as_deref(), attempts) {
+        Ok(accounts::Change::LoggedIn(account, new_token)) => {
+            let username = account.username.clone();
+            match lobby.set_account(client, Some(account)) {
+                Ok(()) => {
+                    *token = Some(new_token.clone());
+                    ServerMessage::LoggedIn { username, token: new_token }
                 },
-                Err(tungstenite::Error::Io(_)) => (), // read timeout,
                 Err(e) => {
-                    // break on other errors
-                    println!("Error: {e}");
-                    break;
+                    // don't leave behind a session nobody was told about
+                    if !resumed {
+                        if let Err(e) = db.delete_session(&new_token) {
+                            log::warn!("Failed to delete session for {username}: {e}");
+                        }
+                    }
+                    ServerMessage::AccountError(e)
                 },
             }
-
-            // receive data through channel from game controller
-            if let Ok(send) = rx_t.try_recv() {
-                websocket.write_message(tungstenite::Message::binary(send)).unwrap();
+        },
+        Ok(accounts::Change::LoggedOut) => {
+            // the session is gone from the database either way
+            *token = None;
+            match lobby.set_account(client, None) {
+                Ok(()) => ServerMessage::LoggedOut,
+                Err(e) => ServerMessage::AccountError(e),
             }
-        }
-    });
-
-    lobby.add_and_print_connections(games::Player::new(t, client, tx, rx));
+        },
+        Err(e) => ServerMessage::AccountError(e),
+    }
 }
@@ -0,0 +1,113 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use common::MAX_CHAT_LEN;
+
+/// Each connection can send at most `RATE_LIMIT`
+/// chat messages within every `RATE_WINDOW`.
+const RATE_LIMIT: usize = 5;
+const RATE_WINDOW: Duration = Duration::from_secs(10);
+
+/// Words replaced with asterisks before a message is sent on.
+/// Longer words starting with one of these are filtered too.
+const FILTERED_WORDS: [&str; 6] = ["fuck", "shit", "cunt", "bitch", "bastard", "wank"];
+
+/// Tracks the recent chat messages of a single connection.
+pub struct Limiter {
+    sent: VecDeque<Instant>,
+}
+
+impl Limiter {
+    pub fn new() -> Self {
+        Limiter {
+            sent: VecDeque::new(),
+        }
+    }
+
+    /// Records a new message, failing if the
+    /// connection has sent too many recently.
+    pub fn check(&mut self) -> Result<(), String> {
+        let now = Instant::now();
+        while self.sent.front().is_some_and(|sent| now.duration_since(*sent) > RATE_WINDOW) {
+            self.sent.pop_front();
+        }
+
+        if self.sent.len() >= RATE_LIMIT {
+            Err("You are sending messages too quickly".to_string())
+        } else {
+            self.sent.push_back(now);
+            Ok(())
+        }
+    }
+}
+
+/// Trims and filters a chat message, rejecting
+/// ones that are empty or too long.
+pub fn clean(text: &str) -> Result<String, String> {
+    let text = text.trim();
+    if text.is_empty() {
+        Err("Can't send an empty message".to_string())
+    } else if text.chars().count() > MAX_CHAT_LEN {
+        Err(format!("Messages can't be longer than {MAX_CHAT_LEN} characters"))
+    } else {
+        Ok(text.split(' ').map(filter_word).collect::<Vec<_>>().join(" "))
+    }
+}
+
+fn filter_word(word: &str) -> String {
+    let letters: String = word.chars().filter(|c| c.is_alphanumeric()).collect::<String>().to_lowercase();
+    if FILTERED_WORDS.iter().any(|filtered| letters.starts_with(filtered)) {
+        word.chars().map(|c| if c.is_alphanumeric() { '*' } else { c }).collect()
+    } else {
+        word.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_messages_within_the_window() {
+        let mut limiter = Limiter::new();
+        for _ in 0..RATE_LIMIT {
+            assert!(limiter.check().is_ok());
+        }
+        assert!(limiter.check().is_err());
+        // refused messages don't count towards the limit
+        assert_eq!(limiter.sent.len(), RATE_LIMIT);
+    }
+
+    #[test]
+    fn allows_messages_again_after_the_window() {
+        let mut limiter = Limiter::new();
+        // as if the messages were sent just over a window ago
+        let long_ago = Instant::now() - RATE_WINDOW - Duration::from_millis(1);
+        limiter.sent.extend([long_ago; RATE_LIMIT]);
+        assert!(limiter.check().is_ok());
+        assert_eq!(limiter.sent.len(), 1);
+    }
+
+    #[test]
+    fn masks_filtered_words() {
+        assert_eq!(clean("oh shit").unwrap(), "oh ****");
+        assert_eq!(clean("SHIT, Shitty!").unwrap(), "****, ******!");
+        assert_eq!(clean("\"fuck\" off").unwrap(), "\"****\" off");
+        assert_eq!(clean("  good game  ").unwrap(), "good game");
+    }
+
+    #[test]
+    fn only_masks_words_starting_with_a_filtered_one() {
+        assert_eq!(clean("Scunthorpe bullshit").unwrap(), "Scunthorpe bullshit");
+        assert_eq!(clean("shi t").unwrap(), "shi t");
+    }
+
+    #[test]
+    fn rejects_empty_and_long_messages() {
+        assert!(clean("   ").is_err());
+        assert!(clean(&"a".repeat(MAX_CHAT_LEN)).is_ok());
+        assert!(clean(&"a".repeat(MAX_CHAT_LEN + 1)).is_err());
+    }
+}
@@ -0,0 +1,428 @@
+//! Server settings, read from an optional TOML config file with any flags
+//! given on the command line taking precedence. See `server.example.toml`
+//! for every setting the file can contain.
+
+use std::{
+    fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::PathBuf,
+    str::FromStr,
+    time::Duration,
+};
+
+use clap::{CommandFactory, Parser};
+use log::LevelFilter;
+use serde::Deserialize;
+
+use common::{tic_tac_toe, TimeControl};
+use common::ai::Difficulty;
+
+use crate::games::{AutoBot, GameSettings};
+use crate::{games, tls};
+
+#[derive(Parser)]
+#[command(name = "server", about = "Websocket server for online board games")]
+pub struct Args {
+    /// Port to listen on [default: 3334]
+    port: Option<u16>,
+
+    /// TOML file to read the settings from,
+    /// flags given here take precedence
+    #[arg(long, short, value_name = "FILE", env = "BOARD_GAMES_CONFIG")]
+    config: Option<PathBuf>,
+
+    /// Address to listen on [default: 0.0.0.0]
+    #[arg(long, value_name = "IP")]
+    address: Option<IpAddr>,
+
+    /// SQLite file used to store player accounts,
+    /// created if it doesn't exist [default: board-games.db]
+    #[arg(long, value_name = "FILE")]
+    database: Option<PathBuf>,
+
+    /// Least important messages to log, one of off, error, warn,
+    /// info, debug or trace. `RUST_LOG` takes precedence [default: info]
+    #[arg(long, value_name = "LEVEL")]
+    log: Option<LevelFilter>,
+
+    /// Prefer pairing players with similar ratings
+    /// over the next player waiting in the lobby
+    #[arg(long)]
+    rating_matchmaking: bool,
+
+    /// Time limit for every game, e.g. `30s` for 30 seconds per move
+    /// or `5+3` for 5 minutes each plus 3 seconds after every move.
+    /// Players who run out of time lose. Unlimited by default
+    #[arg(long, value_name = "LIMIT")]
+    time_control: Option<TimeControl>,
+
+    /// Board to play tic tac toe on, from 3x3 up to 9x9 [default: 3x3]
+    #[arg(long)]
+    variant: Option<String>,
+
+    /// Seconds a player waits for an opponent before
+    /// playing a bot instead. Never by default
+    #[arg(long, value_name = "SECONDS")]
+    bot_after: Option<u64>,
+
+    /// Difficulty of the bots filling in for missing opponents,
+    /// one of random, greedy or minimax [default: minimax]
+    #[arg(long, value_name = "DIFFICULTY")]
+    bot_difficulty: Option<Difficulty>,
+
+    /// Serve plain websockets (`ws://`) without TLS, e.g. for local
+    /// development or behind a reverse proxy that handles TLS
+    #[arg(long)]
+    plain: bool,
+
+    /// PEM certificate chain, e.g. certbot's `fullchain.pem`,
+    /// used instead of the keystore
+    #[arg(long, value_name = "FILE")]
+    cert: Option<PathBuf>,
+
+    /// PEM private key in PKCS #8 format for `--cert`
+    #[arg(long, value_name = "FILE")]
+    key: Option<PathBuf>,
+
+    /// PKCS #12 keystore with the certificate and
+    /// private key [default: tls/keystore.pkcs]
+    #[arg(long, value_name = "FILE")]
+    keystore: Option<PathBuf>,
+
+    /// Password for the keystore
+    #[arg(long, env = "KEYSTORE_PASSWORD", hide_env_values = true)]
+    keystore_password: Option<String>,
+
+    /// Most players connected at once, including
+    /// spectators. Unlimited by default
+    #[arg(long, value_name = "PLAYERS")]
+    max_players: Option<usize>,
+
+    /// Milliseconds between game threads checking for moves [default: 100]
+    #[arg(long, value_name = "MS")]
+    tick: Option<u64>,
+
+    /// Milliseconds between sending the server status
+    /// to every player [default: 1000]
+    #[arg(long, value_name = "MS")]
+    ping_interval: Option<u64>,
+
+    /// Seconds games in progress have to finish when the
+    /// server is shutting down before they are aborted [default: 60]
+    #[arg(long, value_name = "SECONDS")]
+    shutdown_grace: Option<u64>,
+
+    /// Seconds without hearing from a client before
+    /// pinging it to check it is still there [default: 10]
+    #[arg(long, value_name = "SECONDS")]
+    heartbeat: Option<u64>,
+
+    /// Seconds without hearing from a client before it is
+    /// disconnected, must be longer than the heartbeat [default: 30]
+    #[arg(long, value_name = "SECONDS")]
+    idle_timeout: Option<u64>,
+}
+
+/// Contents of the config file, every setting is optional.
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct File {
+    address: Option<IpAddr>,
+    port: Option<u16>,
+    database: Option<PathBuf>,
+    log: Option<String>,
+    tls: TlsFile,
+    matchmaking: MatchmakingFile,
+    limits: LimitsFile,
+    games: GamesFile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct TlsFile {
+    plain: bool,
+    cert: Option<PathBuf>,
+    key: Option<PathBuf>,
+    keystore: Option<PathBuf>,
+    keystore_password: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct MatchmakingFile {
+    rating: bool,
+    bot_after: Option<u64>,
+    bot_difficulty: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct LimitsFile {
+    max_players: Option<usize>,
+    tick_ms: Option<u64>,
+    ping_interval_ms: Option<u64>,
+    shutdown_grace_secs: Option<u64>,
+    heartbeat_secs: Option<u64>,
+    idle_timeout_secs: Option<u64>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct GamesFile {
+    tic_tac_toe: GameFile,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct GameFile {
+    enabled: Option<bool>,
+    variant: Option<String>,
+    time_control: Option<String>,
+}
+
+/// Everything the server needs to start, after checking the settings make sense.
+pub struct Settings {
+    pub address: SocketAddr,
+    pub database: PathBuf,
+    pub log: LevelFilter,
+    /// Certificate for serving websockets over TLS, plain websockets if `None`.
+    pub keys: Option<tls::Keys>,
+    pub heartbeat: Heartbeat,
+    pub lobby: games::Settings,
+}
+
+/// Websocket pings that find clients which have gone without closing
+/// the connection, e.g. after losing their network.
+#[derive(Clone, Copy)]
+pub struct Heartbeat {
+    /// Time without hearing from a client before it is pinged.
+    pub interval: Duration,
+    /// Time without hearing from a client before it is disconnected.
+    pub timeout: Duration,
+}
+
+impl Settings {
+    /// Parses the command line and config file,
+    /// exiting with a usage error if either is invalid.
+    pub fn load() -> Self {
+        let args = Args::parse();
+        Settings::new(args).unwrap_or_else(|e| {
+            Args::command().error(clap::error::ErrorKind::ValueValidation, e).exit()
+        })
+    }
+
+    fn new(args: Args) -> Result<Self, String> {
+        let file = match &args.config {
+            Some(path) => {
+                let text = fs::read_to_string(path).map_err(|e| format!("Unable to read {}. {e}", path.display()))?;
+                toml::from_str(&text).map_err(|e| format!("Invalid config file {}. {e}", path.display()))?
+            },
+            None => File::default(),
+        };
+        Settings::merge(args, file)
+    }
+
+    /// Combines the command line with the config file, checking the result.
+    fn merge(args: Args, file: File) -> Result<Self, String> {
+        let address = args.address.or(file.address).unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+        let port = args.port.or(file.port).unwrap_or(common::REMOTE_PORT);
+        let log = match (args.log, file.log) {
+            (Some(log), _) => log,
+            (None, Some(log)) => parse(&log, "log")?,
+            (None, None) => LevelFilter::Info,
+        };
+
+        let plain = args.plain || file.tls.plain;
+        let cert = args.cert.or(file.tls.cert);
+        let key = args.key.or(file.tls.key);
+        let keys = match (plain, cert, key) {
+            (true, None, None) => None,
+            (true, _, _) => return Err("A certificate can't be used with plain websockets".to_string()),
+            (false, Some(cert), Some(key)) => Some(tls::Keys::Pem { cert, key }),
+            (false, Some(_), None) => return Err("The certificate needs a key".to_string()),
+            (false, None, Some(_)) => return Err("The key needs a certificate".to_string()),
+            (false, None, None) => Some(tls::Keys::Keystore {
+                path: args.keystore.or(file.tls.keystore).unwrap_or_else(|| PathBuf::from("tls/keystore.pkcs")),
+                password: args.keystore_password.or(file.tls.keystore_password).unwrap_or_default(),
+            }),
+        };
+
+        let bot_difficulty = match (args.bot_difficulty, file.matchmaking.bot_difficulty) {
+            (Some(difficulty), _) => difficulty,
+            (None, Some(difficulty)) => parse(&difficulty, "matchmaking.bot_difficulty")?,
+            (None, None) => Difficulty::Minimax,
+        };
+        let auto_bot = args.bot_after.or(file.matchmaking.bot_after).map(|secs| AutoBot {
+            after: Duration::from_secs(secs),
+            difficulty: bot_difficulty,
+        });
+
+        let tic_tac_toe = file.games.tic_tac_toe;
+        let variant = args.variant.or(tic_tac_toe.variant).unwrap_or_else(|| tic_tac_toe::variant(tic_tac_toe::BOARD_SIZE));
+        let board_size = tic_tac_toe::parse_variant(&variant)
+            .ok_or_else(|| format!("Invalid variant `{variant}`, expected a square board from 3x3 to 9x9"))?;
+        let time_control = match (args.time_control, tic_tac_toe.time_control) {
+            (Some(time_control), _) => Some(time_control),
+            (None, Some(time_control)) => Some(parse(&time_control, "games.tic_tac_toe.time_control")?),
+            (None, None) => None,
+        };
+        let tic_tac_toe = GameSettings {
+            enabled: tic_tac_toe.enabled.unwrap_or(true),
+            board_size,
+            time_control,
+        };
+        if !tic_tac_toe.enabled {
+            return Err("At least one game needs to be enabled".to_string());
+        }
+
+        let max_players = args.max_players.or(file.limits.max_players);
+        if max_players == Some(0) {
+            return Err("max_players needs to be at least 1".to_string());
+        }
+        let tick = args.tick.or(file.limits.tick_ms).map_or(common::THREAD_SLEEP, Duration::from_millis);
+        if tick.is_zero() || tick > Duration::from_secs(1) {
+            return Err(format!("The tick needs to be between 1 and 1000 ms, not {} ms", tick.as_millis()));
+        }
+        let ping_interval = args.ping_interval.or(file.limits.ping_interval_ms).map_or(common::PING_INTERVAL, Duration::from_millis);
+        if ping_interval < tick {
+            return Err(format!("The ping interval needs to be at least the tick of {} ms", tick.as_millis()));
+        }
+
+        let heartbeat = Heartbeat {
+            interval: Duration::from_secs(args.heartbeat.or(file.limits.heartbeat_secs).unwrap_or(10)),
+            timeout: Duration::from_secs(args.idle_timeout.or(file.limits.idle_timeout_secs).unwrap_or(30)),
+        };
+        if heartbeat.interval.is_zero() {
+            return Err("The heartbeat needs to be at least 1 second".to_string());
+        }
+        if heartbeat.timeout <= heartbeat.interval {
+            return Err(format!("The idle timeout needs to be longer than the heartbeat of {} s", heartbeat.interval.as_secs()));
+        }
+
+        Ok(Settings {
+            address: SocketAddr::new(address, port),
+            database: args.database.or(file.database).unwrap_or_else(|| PathBuf::from("board-games.db")),
+            log,
+            keys,
+            heartbeat,
+            lobby: games::Settings {
+                rating_matchmaking: args.rating_matchmaking || file.matchmaking.rating,
+                auto_bot,
+                tic_tac_toe,
+                max_players,
+                tick,
+                ping_interval,
+                shutdown_grace: Duration::from_secs(args.shutdown_grace.or(file.limits.shutdown_grace_secs).unwrap_or(60)),
+            },
+        })
+    }
+}
+
+/// Parses a setting from the config file, naming it in the error.
+fn parse<T: FromStr>(value: &str, setting: &str) -> Result<T, String>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e| format!("Invalid {setting} `{value}`. {e}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Args {
+        Args::try_parse_from(["server"].iter().chain(flags)).unwrap()
+    }
+
+    fn file(text: &str) -> File {
+        toml::from_str(text).unwrap()
+    }
+
+    fn merged(flags: &[&str], text: &str) -> Settings {
+        match Settings::merge(args(flags), file(text)) {
+            Ok(settings) => settings,
+            Err(e) => panic!("rejected {flags:?} with {text:?}. {e}"),
+        }
+    }
+
+    fn error(flags: &[&str], text: &str) -> String {
+        match Settings::merge(args(flags), file(text)) {
+            Ok(_) => panic!("accepted {flags:?} with {text:?}"),
+            Err(e) => e,
+        }
+    }
+
+    #[test]
+    fn flags_override_the_file() {
+        let text = "
+            port = 4000
+            database = \"file.db\"
+
+            [limits]
+            heartbeat_secs = 5
+            idle_timeout_secs = 20
+
+            [games.tic_tac_toe]
+            variant = \"4x4\"
+        ";
+        let settings = merged(&["5000", "--plain", "--heartbeat", "8", "--variant", "5x5"], text);
+        assert_eq!(settings.address.port(), 5000);
+        assert_eq!(settings.heartbeat.interval, Duration::from_secs(8));
+        assert_eq!(settings.lobby.tic_tac_toe.board_size, 5);
+        // settings only in the file are still used
+        assert_eq!(settings.database, PathBuf::from("file.db"));
+        assert_eq!(settings.heartbeat.timeout, Duration::from_secs(20));
+
+        let settings = merged(&["--plain"], text);
+        assert_eq!(settings.address.port(), 4000);
+        assert_eq!(settings.lobby.tic_tac_toe.board_size, 4);
+    }
+
+    #[test]
+    fn plain_mode_needs_no_keys() {
+        let settings = merged(&["--plain"], "");
+        assert!(settings.keys.is_none());
+        assert_eq!(settings.address, SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), common::REMOTE_PORT));
+        assert_eq!(settings.database, PathBuf::from("board-games.db"));
+        assert_eq!(settings.heartbeat.interval, Duration::from_secs(10));
+        assert_eq!(settings.heartbeat.timeout, Duration::from_secs(30));
+        assert!(settings.lobby.auto_bot.is_none());
+
+        let settings = merged(&[], "[tls]\nplain = true");
+        assert!(settings.keys.is_none());
+        error(&["--plain", "--cert", "cert.pem", "--key", "key.pem"], "");
+    }
+
+    #[test]
+    fn tls_needs_both_pem_files() {
+        assert!(matches!(merged(&[], "").keys, Some(tls::Keys::Keystore { .. })));
+        assert!(matches!(merged(&["--cert", "cert.pem", "--key", "key.pem"], "").keys, Some(tls::Keys::Pem { .. })));
+        error(&["--cert", "cert.pem"], "");
+        error(&[], "[tls]\nkey = \"key.pem\"");
+    }
+
+    #[test]
+    fn rejects_bad_ports() {
+        assert!(Args::try_parse_from(["server", "70000"]).is_err());
+        assert!(Args::try_parse_from(["server", "port"]).is_err());
+        assert!(toml::from_str::<File>("port = 70000").is_err());
+        assert!(toml::from_str::<File>("port = -1").is_err());
+    }
+
+    #[test]
+    fn rejects_bad_timeouts() {
+        error(&["--plain", "--heartbeat", "0"], "");
+        error(&["--plain", "--heartbeat", "30"], "");
+        error(&["--plain", "--idle-timeout", "5"], "");
+        error(&["--plain"], "[limits]\nheartbeat_secs = 20\nidle_timeout_secs = 10");
+        error(&["--plain", "--tick", "0"], "");
+        error(&["--plain", "--tick", "50", "--ping-interval", "10"], "");
+        error(&["--plain"], "[games.tic_tac_toe]\ntime_control = \"0+5\"");
+        assert!(Args::try_parse_from(["server", "--heartbeat", "-1"]).is_err());
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(toml::from_str::<File>("[limits]\nheartbeat = 5").is_err());
+    }
+}
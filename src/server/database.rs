@@ -0,0 +1,393 @@
+use std::{
+    collections::HashMap,
+    path::Path,
+    sync::{Arc, Mutex},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use rusqlite::{params, Connection, OptionalExtension, TransactionBehavior};
+
+use common::{Game, Ranking};
+use common::history::{GameRecord, GameSummary};
+use common::tic_tac_toe::{End, Piece};
+
+use crate::accounts::Account;
+use crate::error::lock;
+use crate::games::Snapshot;
+use crate::ratings::Rating;
+
+/// Tables are only created if they don't exist yet
+/// so the same file can be reused between restarts.
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS accounts (
+        id            INTEGER PRIMARY KEY,
+        username      TEXT NOT NULL UNIQUE COLLATE NOCASE,
+        password_hash TEXT NOT NULL,
+        created       INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS bots (
+        account_id INTEGER PRIMARY KEY REFERENCES accounts(id) ON DELETE CASCADE,
+        flagged    INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS sessions (
+        token      TEXT PRIMARY KEY,
+        account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+        created    INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS ratings (
+        account_id INTEGER NOT NULL REFERENCES accounts(id) ON DELETE CASCADE,
+        game       TEXT NOT NULL,
+        rating     REAL NOT NULL,
+        games      INTEGER NOT NULL,
+        PRIMARY KEY (account_id, game)
+    );
+    CREATE TABLE IF NOT EXISTS games (
+        id              INTEGER PRIMARY KEY,
+        game            TEXT NOT NULL,
+        variant         TEXT NOT NULL,
+        player1         TEXT NOT NULL,
+        player2         TEXT NOT NULL,
+        player1_account INTEGER REFERENCES accounts(id) ON DELETE SET NULL,
+        player2_account INTEGER REFERENCES accounts(id) ON DELETE SET NULL,
+        started         INTEGER NOT NULL,
+        ended           INTEGER NOT NULL,
+        result          TEXT NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS moves (
+        game_id INTEGER NOT NULL REFERENCES games(id) ON DELETE CASCADE,
+        ply     INTEGER NOT NULL,
+        piece   TEXT NOT NULL,
+        x       INTEGER NOT NULL,
+        y       INTEGER NOT NULL,
+        PRIMARY KEY (game_id, ply)
+    );
+    CREATE TABLE IF NOT EXISTS snapshots (
+        session_id INTEGER PRIMARY KEY,
+        data       BLOB NOT NULL,
+        updated    INTEGER NOT NULL
+    );
+";
+
+/// Handle to the local SQLite database. Cloning the handle
+/// shares the same underlying connection between threads.
+#[derive(Clone)]
+pub struct Database {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    /// Opens the database file at `path`, creating
+    /// it and any missing tables if needed.
+    pub fn open(path: &Path) -> rusqlite::Result<Self> {
+        let conn = Connection::open(path)?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+
+        Ok(Database {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// Inserts a new account, returning `None` if
+    /// the username is already taken.
+    pub fn create_account(&self, username: &str, password_hash: &str) -> rusqlite::Result<Option<Account>> {
+        let conn = lock(&self.conn);
+        let inserted = conn.execute(
+            "INSERT OR IGNORE INTO accounts (username, password_hash, created) VALUES (?1, ?2, ?3)",
+            params![username, password_hash, now()],
+        )?;
+
+        if inserted == 0 {
+            Ok(None)
+        } else {
+            Ok(Some(Account {
+                id: conn.last_insert_rowid(),
+                username: username.to_owned(),
+                bot: false,
+                ratings: HashMap::new(),
+            }))
+        }
+    }
+
+    /// Looks up an account by username, returning
+    /// it along with the stored password hash.
+    pub fn find_account(&self, username: &str) -> rusqlite::Result<Option<(Account, String)>> {
+        let conn = lock(&self.conn);
+        conn.query_row(
+            "SELECT accounts.id, accounts.username, bots.account_id IS NOT NULL, accounts.password_hash FROM accounts
+             LEFT JOIN bots ON bots.account_id = accounts.id
+             WHERE accounts.username = ?1",
+            params![username],
+            |row| Ok((account(row)?, row.get(3)?)),
+        ).optional()
+    }
+
+    pub fn create_session(&self, account: &Account, token: &str) -> rusqlite::Result<()> {
+        let conn = lock(&self.conn);
+        conn.execute(
+            "INSERT INTO sessions (token, account_id, created) VALUES (?1, ?2, ?3)",
+            params![token, account.id, now()],
+        )?;
+        Ok(())
+    }
+
+    /// Finds the account for a session token, ignoring
+    /// any sessions created before `since`.
+    pub fn find_session(&self, token: &str, since: u64) -> rusqlite::Result<Option<Account>> {
+        let conn = lock(&self.conn);
+        conn.query_row(
+            "SELECT accounts.id, accounts.username, bots.account_id IS NOT NULL FROM sessions
+             JOIN accounts ON accounts.id = sessions.account_id
+             LEFT JOIN bots ON bots.account_id = accounts.id
+             WHERE sessions.token = ?1 AND sessions.created >= ?2",
+            params![token, since],
+            account,
+        ).optional()
+    }
+
+    /// Marks the account as being played by a program.
+    pub fn flag_bot(&self, account: &Account) -> rusqlite::Result<()> {
+        let conn = lock(&self.conn);
+        conn.execute(
+            "INSERT OR IGNORE INTO bots (account_id, flagged) VALUES (?1, ?2)",
+            params![account.id, now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_session(&self, token: &str) -> rusqlite::Result<()> {
+        let conn = lock(&self.conn);
+        conn.execute("DELETE FROM sessions WHERE token = ?1", params![token])?;
+        Ok(())
+    }
+
+    /// Stored rating for an account, `None` if they haven't played a rated game.
+    pub fn rating(&self, account: &Account, game: Game) -> rusqlite::Result<Option<Rating>> {
+        let conn = lock(&self.conn);
+        conn.query_row(
+            "SELECT rating, games FROM ratings WHERE account_id = ?1 AND game = ?2",
+            params![account.id, game_key(game)],
+            |row| Ok(Rating { rating: row.get(0)?, games: row.get(1)? }),
+        ).optional()
+    }
+
+    /// Rates a game between two accounts in a single transaction, so the
+    /// ratings are read as they are now rather than when the game started,
+    /// e.g. if either account finished another game in the meantime.
+    /// `rate` is given both current ratings and returns the new ones,
+    /// which are saved and returned along with the old ones.
+    pub fn update_ratings(
+        &self,
+        game: Game,
+        accounts: [&Account; 2],
+        rate: impl FnOnce([Rating; 2]) -> [Rating; 2],
+    ) -> rusqlite::Result<([Rating; 2], [Rating; 2])> {
+        let mut conn = lock(&self.conn);
+        let tx = conn.transaction_with_behavior(TransactionBehavior::Immediate)?;
+        let mut old = [Rating::default(); 2];
+        for (account, rating) in accounts.iter().zip(&mut old) {
+            let stored = tx.query_row(
+                "SELECT rating, games FROM ratings WHERE account_id = ?1 AND game = ?2",
+                params![account.id, game_key(game)],
+                |row| Ok(Rating { rating: row.get(0)?, games: row.get(1)? }),
+            ).optional()?;
+            *rating = stored.unwrap_or_default();
+        }
+
+        let new = rate(old);
+        for (account, rating) in accounts.iter().zip(new) {
+            tx.execute(
+                "INSERT OR REPLACE INTO ratings (account_id, game, rating, games) VALUES (?1, ?2, ?3, ?4)",
+                params![account.id, game_key(game), rating.rating, rating.games],
+            )?;
+        }
+        tx.commit()?;
+        Ok((old, new))
+    }
+
+    pub fn leaderboard(&self, game: Game, limit: usize) -> rusqlite::Result<Vec<Ranking>> {
+        let conn = lock(&self.conn);
+        let mut stmt = conn.prepare(
+            "SELECT accounts.username, ratings.rating, ratings.games FROM ratings
+             JOIN accounts ON accounts.id = ratings.account_id
+             WHERE ratings.game = ?1
+             ORDER BY ratings.rating DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![game_key(game), limit], |row| {
+            Ok(Ranking {
+                username: row.get(0)?,
+                rating: row.get::<_, f64>(1)?.round() as u32,
+                games: row.get(2)?,
+            })
+        })?;
+        rows.collect()
+    }
+
+    /// Saves a finished game and its moves, returning the new game id.
+    /// `accounts` links each player to their account, if they had one.
+    pub fn save_game(&self, record: &GameRecord, accounts: [Option<i64>; 2]) -> rusqlite::Result<i64> {
+        let mut conn = lock(&self.conn);
+        let tx = conn.transaction()?;
+        let summary = &record.summary;
+        tx.execute(
+            "INSERT INTO games (game, variant, player1, player2, player1_account, player2_account, started, ended, result)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+            params![
+                game_key(summary.game), summary.variant,
+                summary.players[0], summary.players[1],
+                accounts[0], accounts[1],
+                summary.started, summary.ended,
+                end_key(&summary.result),
+            ],
+        )?;
+        let id = tx.last_insert_rowid();
+
+        for (ply, (piece, x, y)) in record.moves.iter().enumerate() {
+            tx.execute(
+                "INSERT INTO moves (game_id, ply, piece, x, y) VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![id, ply, piece.to_string(), x, y],
+            )?;
+        }
+        tx.commit()?;
+        Ok(id)
+    }
+
+    /// Most recent games first, only those played by
+    /// the account with `username` if there is one.
+    pub fn games(&self, username: Option<&str>, limit: usize) -> rusqlite::Result<Vec<GameSummary>> {
+        let conn = lock(&self.conn);
+        let mut stmt = conn.prepare(
+            "SELECT id, game, variant, player1, player2, started, ended, result FROM games
+             WHERE ?1 IS NULL
+                OR player1_account = (SELECT id FROM accounts WHERE username = ?1)
+                OR player2_account = (SELECT id FROM accounts WHERE username = ?1)
+             ORDER BY id DESC LIMIT ?2",
+        )?;
+        let rows = stmt.query_map(params![username, limit], summary)?;
+        rows.collect()
+    }
+
+    pub fn game_record(&self, id: i64) -> rusqlite::Result<Option<GameRecord>> {
+        let conn = lock(&self.conn);
+        let summary = conn.query_row(
+            "SELECT id, game, variant, player1, player2, started, ended, result FROM games WHERE id = ?1",
+            params![id],
+            summary,
+        ).optional()?;
+
+        let Some(summary) = summary else { return Ok(None) };
+
+        let mut stmt = conn.prepare("SELECT piece, x, y FROM moves WHERE game_id = ?1 ORDER BY ply")?;
+        let moves = stmt.query_map(params![id], |row| {
+            Ok((parse_piece(&row.get::<_, String>(0)?), row.get(1)?, row.get(2)?))
+        })?.collect::<rusqlite::Result<_>>()?;
+
+        Ok(Some(GameRecord { summary, moves }))
+    }
+
+    /// Saves a game in progress, replacing its last snapshot.
+    pub fn save_snapshot(&self, snapshot: &Snapshot) -> rusqlite::Result<()> {
+        let data = bincode::serialize(snapshot).map_err(|e| rusqlite::Error::ToSqlConversionFailure(e))?;
+        let conn = lock(&self.conn);
+        conn.execute(
+            "INSERT OR REPLACE INTO snapshots (session_id, data, updated) VALUES (?1, ?2, ?3)",
+            params![snapshot.id as i64, data, now()],
+        )?;
+        Ok(())
+    }
+
+    pub fn delete_snapshot(&self, session: u64) -> rusqlite::Result<()> {
+        let conn = lock(&self.conn);
+        conn.execute("DELETE FROM snapshots WHERE session_id = ?1", params![session as i64])?;
+        Ok(())
+    }
+
+    /// Games that were in progress when the server stopped. Snapshots
+    /// that can't be read, e.g. from an older version, are skipped.
+    pub fn snapshots(&self) -> rusqlite::Result<Vec<Snapshot>> {
+        let conn = lock(&self.conn);
+        let mut stmt = conn.prepare("SELECT session_id, data FROM snapshots ORDER BY session_id")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, Vec<u8>>(1)?)))?;
+
+        let mut snapshots = Vec::new();
+        for row in rows {
+            let (id, data) = row?;
+            match bincode::deserialize(&data) {
+                Ok(snapshot) => snapshots.push(snapshot),
+                Err(e) => log::warn!("Skipping unreadable snapshot of session {id}. {e}"),
+            }
+        }
+        Ok(snapshots)
+    }
+}
+
+fn summary(row: &rusqlite::Row) -> rusqlite::Result<GameSummary> {
+    Ok(GameSummary {
+        id: row.get(0)?,
+        game: parse_game(&row.get::<_, String>(1)?),
+        variant: row.get(2)?,
+        players: [row.get(3)?, row.get(4)?],
+        started: row.get(5)?,
+        ended: row.get(6)?,
+        result: parse_end(&row.get::<_, String>(7)?),
+    })
+}
+
+fn account(row: &rusqlite::Row) -> rusqlite::Result<Account> {
+    Ok(Account {
+        id: row.get(0)?,
+        username: row.get(1)?,
+        bot: row.get(2)?,
+        ratings: HashMap::new(),
+    })
+}
+
+/// Key used to store each game in the database. These
+/// need to stay the same even if the enum is renamed.
+fn game_key(game: Game) -> &'static str {
+    match game {
+        Game::TicTacToe => "tic_tac_toe",
+    }
+}
+
+fn parse_game(key: &str) -> Game {
+    Game::ALL.into_iter().find(|game| game_key(*game) == key).unwrap_or(Game::TicTacToe)
+}
+
+/// Results are stored as text so they can be read straight from the database.
+fn end_key(end: &End) -> String {
+    match end {
+        End::Victory(piece) => format!("victory {piece}"),
+        End::Draw => "draw".to_string(),
+        End::Disconnect => "disconnect".to_string(),
+        End::Timeout(piece) => format!("timeout {piece}"),
+        End::Resignation(piece) => format!("resignation {piece}"),
+        End::AgreedDraw => "agreed_draw".to_string(),
+        End::Aborted => "aborted".to_string(),
+    }
+}
+
+fn parse_end(key: &str) -> End {
+    match key.split_once(' ') {
+        Some(("victory", piece)) => End::Victory(parse_piece(piece)),
+        Some(("timeout", piece)) => End::Timeout(parse_piece(piece)),
+        Some(("resignation", piece)) => End::Resignation(parse_piece(piece)),
+        _ if key == "draw" => End::Draw,
+        _ if key == "agreed_draw" => End::AgreedDraw,
+        _ if key == "aborted" => End::Aborted,
+        _ => End::Disconnect,
+    }
+}
+
+fn parse_piece(piece: &str) -> Piece {
+    match piece {
+        "X" => Piece::Cross,
+        "O" => Piece::Nought,
+        _ => Piece::Empty,
+    }
+}
+
+/// Current unix time in seconds.
+pub fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_secs()).unwrap_or_default()
+}
@@ -0,0 +1,45 @@
+//! Errors the server recovers from. A failure is logged and only ends the
+//! connection or game it happened in, everyone else carries on.
+
+use std::{
+    fmt,
+    net::SocketAddr,
+    sync::{Mutex, MutexGuard},
+};
+
+#[derive(Debug)]
+pub enum Error {
+    /// The player's connection has closed, so
+    /// messages can no longer be passed to or from them.
+    Disconnected(SocketAddr),
+    /// Boxed as tungstenite's errors are large.
+    Websocket(Box<tungstenite::Error>),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Disconnected(addr) => write!(f, "{addr} has disconnected"),
+            Error::Websocket(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<tungstenite::Error> for Error {
+    fn from(e: tungstenite::Error) -> Self {
+        Error::Websocket(Box::new(e))
+    }
+}
+
+/// Locks the mutex even if a thread panicked while holding it. Every
+/// lock is shared by the whole server, so giving up on it would stop
+/// everyone's games over one player's.
+pub fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| {
+        log::error!("Recovered a lock held by a thread that panicked");
+        mutex.clear_poison();
+        e.into_inner()
+    })
+}
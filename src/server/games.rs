@@ -1,62 +1,245 @@
 use std::{
+    panic::{self, AssertUnwindSafe},
     thread::{self, JoinHandle},
     net::SocketAddr,
-    sync::{Arc, Mutex},
-    sync::mpsc::{Receiver, Sender, SendError, TryRecvError},
+    sync::{Arc, Mutex, OnceLock},
+    sync::atomic::{AtomicBool, AtomicU64, Ordering},
+    sync::mpsc::{Receiver, Sender, TryRecvError},
+    time::{Duration, Instant},
 };
 
-use common::{THREAD_SLEEP, ChannelBuf, ServerStatus};
+use common::{ChatChannel, ChatMessage, Game, LiveGame, ServerStatus, ServerMessage, TimeControl};
+use common::ai::Difficulty;
 
-use common::tic_tac_toe::Message;
+use common::tic_tac_toe::{End, Message};
 
+use crate::accounts::Account;
+use crate::database::Database;
+use crate::error::{lock, Error};
+use crate::history::Recorder;
+
+mod bot;
+mod clock;
+mod snapshot;
 mod tic_tac_toe;
-enum Game {
-    TicTacToe,
+
+pub use snapshot::{Seat, Snapshot};
+
+/// How often players are reminded that the server is shutting down.
+const SHUTDOWN_NOTICE: Duration = Duration::from_secs(10);
+
+/// How long a game restored after a restart waits for its
+/// players to log back in before it is given up on.
+const RESUME_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+/// How long clients have to acknowledge the close
+/// frame before the server exits without them.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Shared by the lobby and every game once the server starts shutting down.
+#[derive(Default)]
+pub struct Shutdown {
+    /// Games still being played at this time are aborted.
+    deadline: OnceLock<Instant>,
+    /// Connections should send a close frame and stop.
+    closed: AtomicBool,
+}
+
+impl Shutdown {
+    pub fn started(&self) -> bool {
+        self.deadline.get().is_some()
+    }
+
+    fn expired(&self) -> bool {
+        self.deadline.get().is_some_and(|deadline| Instant::now() >= *deadline)
+    }
+
+    pub fn closed(&self) -> bool {
+        self.closed.load(Ordering::Relaxed)
+    }
 }
 
 pub struct Session {
+    id: u64,
     player1: SocketAddr,
     player2: SocketAddr,
     game: Option<Game>,
+    board_size: usize,
+    time_control: Option<TimeControl>,
+    /// How long the game thread waits between checking for messages.
+    tick: Duration,
+    shutdown: Arc<Shutdown>,
+    /// Shared list of live games, the session is
+    /// listed from when it begins until it is dropped.
+    live: Arc<Mutex<Vec<LiveGame>>>,
 }
 
 impl Session {
-    pub fn new((player1, player2): (SocketAddr, SocketAddr)) -> Self {
+    pub fn new(id: u64, (player1, player2): (SocketAddr, SocketAddr), settings: GameSettings, tick: Duration, shutdown: Arc<Shutdown>, live: Arc<Mutex<Vec<LiveGame>>>) -> Self {
         Session {
+            id,
             player1,
             player2,
             game: None,
+            board_size: settings.board_size,
+            time_control: settings.time_control,
+            tick,
+            shutdown,
+            live,
         }
     }
 
+    fn is_player(&self, player: &Player) -> bool {
+        player.addr == self.player1 || player.addr == self.player2
+    }
+
+    /// Splits the lobby into the two players of this
+    /// session and anyone spectating it.
+    fn audience<'a>(&self, players: &'a mut [Player]) -> (Vec<&'a mut Player>, Vec<&'a mut Player>) {
+        players
+            .iter_mut()
+            .filter(|player| self.is_player(player) || player.spectating == Some(self.id))
+            .partition(|player| self.is_player(player))
+    }
+
+    /// Lists the game so it can be found by spectators.
+    fn register(&self, players: [String; 2]) {
+        if let Some(game) = self.game {
+            lock(&self.live).push(LiveGame {
+                id: self.id,
+                game,
+                players,
+                spectators: 0,
+            });
+        }
+    }
+
+    fn set_spectators(&self, spectators: usize) {
+        let mut live = lock(&self.live);
+        if let Some(game) = live.iter_mut().find(|game| game.id == self.id) {
+            game.spectators = spectators;
+        }
+    }
+
+    /// Removes the game from the live list. Needs to be
+    /// done before the spectators are let go so nobody
+    /// can start watching a game that has already ended.
+    fn unregister(&self) {
+        lock(&self.live).retain(|game| game.id != self.id);
+    }
+
     // TODO move these to player
-    pub fn send(player: &Player, msg: Message) -> Result<(), SendError<ChannelBuf>> {
-        player.tx.send(dbg!(msg).into())?;
-        Ok(())
+    /// Passes the message to the player's connection. Fails if they have just
+    /// disconnected, which the game or lobby notices the next time it checks.
+    pub fn send(player: &Player, msg: impl Into<common::Message>) -> Result<(), Error> {
+        let msg = msg.into();
+        log::trace!("  {}  <--  {msg:?}", player.addr);
+        player.tx.send(msg.into()).map_err(|_| {
+            log::debug!("Dropped a message for {}, they have disconnected", player.addr);
+            Error::Disconnected(player.addr)
+        })
     }
 
-    pub fn broadcast(player1: &Player, player2: &Player, msg: Message) -> Result<(), SendError<ChannelBuf>> {
-        Session::send(player1, msg.clone())?;
-        Session::send(player2, msg)?;
-        Ok(())
+    /// Sends the message to every player given. Players that have just
+    /// disconnected are skipped, the game notices them on the next loop.
+    pub fn broadcast<'a>(audience: impl IntoIterator<Item = &'a Player>, msg: impl Into<common::Message>) {
+        let msg = msg.into();
+        for player in audience {
+            let _ = Session::send(player, msg.clone());
+        }
     }
 }
 
-/* pub fn try_recv<'a, T: serde::de::Deserialize<'a>>(player: &Player) -> Result<T, TryRecvError> {
-    //player.rx.try_recv().and_then(|msg| Ok(bincode::deserialize(&msg).unwrap()))
-    let res = player.rx.try_recv();
-    match res {
-        Ok(msg) => Ok(bincode::deserialize::<'a, T>(&msg).unwrap()),
-        Err(_) => Err(TryRecvError::Empty),
+impl Drop for Session {
+    fn drop(&mut self) {
+        // also covers game threads that panic
+        self.unregister();
     }
-} */
+}
 
+/// Next game message from the player, if there is one. Only game messages
+/// are forwarded by the connection thread, anything else is logged and skipped.
 pub fn try_recv(player: &Player) -> Result<Message, TryRecvError> {
-    player.rx.try_recv().map(|msg| dbg!(msg.into()))
+    loop {
+        match common::Message::try_from(player.rx.try_recv()?) {
+            Ok(common::Message::TicTacToe(msg)) => {
+                log::trace!("  {}  -->  {msg:?}", player.addr);
+                return Ok(msg);
+            },
+            Ok(msg) => log::warn!("Unexpected message forwarded to the game from {}: {msg:?}", player.addr),
+            Err(e) => log::warn!("Invalid message forwarded to the game from {}. {e}", player.addr),
+        }
+    }
 }
 
+#[derive(Clone)]
 pub struct Lobby {
     players: Arc<Mutex<Vec<Player>>>,
+    /// Games in progress, always locked after `players`
+    /// when both are needed.
+    live: Arc<Mutex<Vec<LiveGame>>>,
+    db: Database,
+    settings: Settings,
+    /// Id of the next session, only taken while holding the players lock.
+    next_id: Arc<AtomicU64>,
+    shutdown: Arc<Shutdown>,
+    /// Games restored after a restart that are waiting for
+    /// their players, always locked after `players`.
+    suspended: Arc<Mutex<Vec<Suspended>>>,
+}
+
+/// A restored game and the players who have come back to it so far.
+struct Suspended {
+    snapshot: Snapshot,
+    /// Addresses of the crosses and noughts players once they have logged in.
+    seats: [Option<SocketAddr>; 2],
+    restored: Instant,
+}
+
+/// How the lobby matches players and runs their games.
+#[derive(Clone)]
+pub struct Settings {
+    /// Pair players with the closest rating
+    /// instead of the next player waiting.
+    pub rating_matchmaking: bool,
+    /// Seat a bot opposite anyone left waiting for too long.
+    pub auto_bot: Option<AutoBot>,
+    pub tic_tac_toe: GameSettings,
+    /// Most players connected at once, unlimited if `None`.
+    pub max_players: Option<usize>,
+    /// How long the lobby and game threads wait between
+    /// checking for players and messages.
+    pub tick: Duration,
+    /// How often every player is sent the server status.
+    pub ping_interval: Duration,
+    /// How long games in progress have to finish when
+    /// the server shuts down before they are aborted.
+    pub shutdown_grace: Duration,
+}
+
+impl Settings {
+    pub fn game(&self, game: Game) -> &GameSettings {
+        match game {
+            Game::TicTacToe => &self.tic_tac_toe,
+        }
+    }
+}
+
+/// Settings for each game the server can host.
+#[derive(Clone, Copy)]
+pub struct GameSettings {
+    pub enabled: bool,
+    pub board_size: usize,
+    /// Time limits for every game, unlimited if `None`.
+    pub time_control: Option<TimeControl>,
+}
+
+/// Bot that fills in for a missing opponent.
+#[derive(Clone, Copy)]
+pub struct AutoBot {
+    /// How long a player waits before playing the bot.
+    pub after: Duration,
+    pub difficulty: Difficulty,
 }
 
 pub struct Player {
@@ -65,6 +248,12 @@ pub struct Player {
     tx: Sender<Vec<u8>>,
     rx: Receiver<Vec<u8>>,
     status: Status,
+    account: Option<Account>,
+    /// Id of the game being watched, players can
+    /// spectate while waiting for their own game.
+    spectating: Option<u64>,
+    /// Set for computer players, which are never matched by the lobby.
+    bot: Option<Difficulty>,
 }
 
 impl Player {
@@ -74,58 +263,410 @@ impl Player {
             addr,
             tx,
             rx,
-            status: Status::Waiting,
+            status: Status::Idle,
+            account: None,
+            spectating: None,
+            bot: None,
+        }
+    }
+
+    /// Name shown to other players. Guests
+    /// are identified by their address.
+    pub fn name(&self) -> String {
+        match (&self.account, self.bot) {
+            (Some(account), _) if account.bot => format!("{} [bot]", account.username),
+            (Some(account), _) => account.username.clone(),
+            (None, Some(difficulty)) => format!("Bot ({difficulty})"),
+            (None, None) => self.addr.to_string(),
+        }
+    }
+
+    pub fn account(&self) -> Option<&Account> {
+        self.account.as_ref()
+    }
+
+    /// Displayed rating, only registered accounts have one.
+    pub fn rating(&self, game: Game) -> Option<u32> {
+        self.account.as_ref().map(|account| account.rating(game).display())
+    }
+
+    /// Guests are matched as if they had the default rating.
+    fn matchmaking_rating(&self, game: Game) -> f64 {
+        self.account.as_ref().map(|account| account.rating(game)).unwrap_or_default().rating
+    }
+
+    /// How the player is recognised after a restart, guests can't be.
+    fn seat(&self) -> Option<Seat> {
+        match (&self.account, self.bot) {
+            (Some(account), _) => Some(Seat::Account(account.id)),
+            (None, Some(difficulty)) => Some(Seat::Bot(difficulty)),
+            (None, None) => None,
+        }
+    }
+
+    fn same_account(&self, other: &Player) -> bool {
+        match (&self.account, &other.account) {
+            (Some(a), Some(b)) => a.id == b.id,
+            _ => false,
         }
     }
 }
 
 #[derive(PartialEq)]
 enum Status {
-    Waiting,
-    Playing,
+    /// Connected but not looking for a game yet.
+    Idle,
+    /// Looking for a game since the given time.
+    Waiting(Instant),
+    /// Playing in the session with this id.
+    Playing(u64),
+    /// Waiting for the opponent in a game restored after a restart.
+    Resuming(u64),
 }
 
 impl Lobby {
-    pub fn new() -> Self {
+    pub fn new(db: Database, settings: Settings) -> Self {
         Lobby {
-            players:Arc::new(Mutex::new(Vec::new()))
+            players:Arc::new(Mutex::new(Vec::new())),
+            live: Arc::new(Mutex::new(Vec::new())),
+            db,
+            settings,
+            next_id: Arc::new(AtomicU64::new(0)),
+            shutdown: Arc::new(Shutdown::default()),
+            suspended: Arc::new(Mutex::new(Vec::new())),
+        }
+    }
+
+    /// Loads the games that were in progress when the server stopped,
+    /// which carry on once their players log back in. Bots are
+    /// started again straight away.
+    pub fn restore(&self) {
+        let snapshots = match self.db.snapshots() {
+            Ok(snapshots) => snapshots,
+            Err(e) => {
+                log::error!("Unable to restore games. {e}");
+                return;
+            },
+        };
+
+        let mut data = lock(&self.players);
+        for snapshot in snapshots {
+            self.next_id.fetch_max(snapshot.id + 1, Ordering::Relaxed);
+            let mut seats = [None; 2];
+            for (seat, addr) in snapshot.seats.iter().zip(seats.iter_mut()) {
+                if let Seat::Bot(difficulty) = seat {
+                    let mut bot = bot::spawn(*difficulty);
+                    bot.status = Status::Resuming(snapshot.id);
+                    *addr = Some(bot.addr);
+                    data.push(bot);
+                }
+            }
+            log::info!("Restored game {} between {} and {}", snapshot.id, snapshot.names[0], snapshot.names[1]);
+            lock(&self.suspended).push(Suspended {
+                snapshot,
+                seats,
+                restored: Instant::now(),
+            });
+        }
+    }
+
+    /// Seats the player at `addr` in their restored game if they have one,
+    /// starting it once both players are back.
+    fn resume(&self, players: &mut [Player], addr: SocketAddr) {
+        let mut suspended = lock(&self.suspended);
+        // forget anyone who has left again since taking their seat
+        for game in suspended.iter_mut() {
+            for seat in game.seats.iter_mut() {
+                if seat.is_some_and(|seat| !players.iter().any(|player| player.addr == seat)) {
+                    *seat = None;
+                }
+            }
+        }
+
+        let Some(player) = players.iter_mut().find(|player| player.addr == addr) else { return };
+        let Some(account) = player.account.as_ref().map(|account| account.id) else { return };
+        let found = suspended.iter().enumerate().find_map(|(i, game)| {
+            let seat = (0..2).find(|&seat| game.snapshot.seats[seat] == Seat::Account(account) && game.seats[seat].is_none())?;
+            Some((i, seat))
+        });
+        let Some((i, seat)) = found else { return };
+
+        let game = &mut suspended[i];
+        game.seats[seat] = Some(addr);
+        player.status = Status::Resuming(game.snapshot.id);
+        player.spectating = None;
+
+        if let [Some(crosses), Some(noughts)] = game.seats {
+            let game = suspended.remove(i);
+            let id = game.snapshot.id;
+            for player in players.iter_mut().filter(|player| player.status == Status::Resuming(id)) {
+                player.status = Status::Playing(id);
+            }
+            log::info!("Resuming game {id}");
+            let settings = GameSettings {
+                enabled: true,
+                board_size: game.snapshot.board_size,
+                time_control: game.snapshot.time_control,
+            };
+            self.start(id, (crosses, noughts), settings, Some(game.snapshot));
+        } else {
+            let opponent = game.snapshot.names[1 - seat].clone();
+            let _ = Session::send(player, ServerMessage::Resuming(game.snapshot.game, opponent));
+        }
+    }
+
+    /// Gives up on restored games whose players haven't come back in time.
+    /// The game is saved as aborted and anyone who did come back is
+    /// put in the matchmaking queue.
+    fn expire_suspended(&self, players: &mut [Player]) {
+        let mut suspended = lock(&self.suspended);
+        let (expired, waiting) = suspended.drain(..).partition(|game| game.restored.elapsed() >= RESUME_TIMEOUT);
+        *suspended = waiting;
+
+        for game in expired {
+            log::info!("Nobody came back to game {}, aborting it", game.snapshot.id);
+            self.abort_suspended(players, game);
+        }
+    }
+
+    /// Gives up the restored game the player at `addr` is waiting in,
+    /// rather than waiting for their opponent to come back.
+    pub fn abandon(&self, addr: SocketAddr) -> Result<(), String> {
+        let mut data = lock(&self.players);
+        let id = match data.iter().find(|player| player.addr == addr).map(|player| &player.status) {
+            Some(&Status::Resuming(id)) => id,
+            Some(_) => return Err("No restored game to abandon".to_string()),
+            None => return Err("Not connected to the lobby".to_string()),
+        };
+        let game = {
+            let mut suspended = lock(&self.suspended);
+            let i = suspended.iter().position(|game| game.snapshot.id == id).ok_or("No restored game to abandon")?;
+            suspended.remove(i)
+        };
+        log::info!("  {addr}  abandoned game {id}");
+        self.abort_suspended(&mut data, game);
+        Ok(())
+    }
+
+    /// Records a restored game as aborted, putting anyone
+    /// who came back to it in the matchmaking queue.
+    fn abort_suspended(&self, players: &mut [Player], game: Suspended) {
+        let id = game.snapshot.id;
+        for player in players.iter_mut().filter(|player| player.status == Status::Resuming(id)) {
+            player.status = Status::Waiting(Instant::now());
+            // also stops bots
+            let _ = Session::send(player, Message::GameOver(End::Aborted));
+        }
+        Recorder::resume(&game.snapshot).save(&self.db, End::Aborted);
+        if let Err(e) = self.db.delete_snapshot(id) {
+            log::error!("Unable to delete the snapshot of game {id}. {e}");
+        }
+    }
+
+    pub fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub fn shutdown(&self) -> &Shutdown {
+        &self.shutdown
+    }
+
+    /// Stops new games from starting and counts down while the games in
+    /// progress finish, aborting any still going at the deadline. Then
+    /// closes every connection, returning once they have all gone.
+    pub fn close(&self) {
+        let grace = self.settings.shutdown_grace;
+        let deadline = Instant::now() + grace;
+        if self.shutdown.deadline.set(deadline).is_err() {
+            return;
+        }
+        log::info!("Shutting down, waiting up to {}s for games to finish", grace.as_secs());
+
+        let mut notified = None;
+        loop {
+            {
+                let data = lock(&self.players);
+                let playing = data.iter().any(|player| matches!(player.status, Status::Playing(_)));
+                // games are aborted at the deadline, the extra time
+                // is in case a game thread has stopped responding
+                if !playing || Instant::now() >= deadline + CLOSE_TIMEOUT {
+                    break;
+                }
+                if notified.is_none_or(|at: Instant| at.elapsed() >= SHUTDOWN_NOTICE) {
+                    notified = Some(Instant::now());
+                    let remaining = deadline.saturating_duration_since(Instant::now());
+                    Session::broadcast(data.iter(), ServerMessage::Shutdown(remaining));
+                }
+            }
+            thread::sleep(self.settings.tick);
+        }
+
+        log::info!("All games have finished, closing connections");
+        // bots of restored games nobody came back to are waiting on their
+        // game, they are started again with it after the restart
+        for player in lock(&self.players).iter_mut() {
+            if matches!(player.status, Status::Resuming(_)) && player.bot.is_some() {
+                player.status = Status::Idle;
+                let _ = Session::send(player, Message::GameOver(End::Aborted));
+            }
+        }
+        self.shutdown.closed.store(true, Ordering::Relaxed);
+        let closing = Instant::now();
+        while !lock(&self.players).is_empty() && closing.elapsed() < CLOSE_TIMEOUT {
+            thread::sleep(self.settings.tick);
         }
     }
 
     pub fn begin_game(&self) {
-        let players = Arc::clone(&self.players);
+        let lobby = self.clone();
 
         thread::spawn(move|| {
             loop {
-                thread::sleep(THREAD_SLEEP);
-                let mut data = players.lock().unwrap();
-                let pair = Lobby::find_pair(&mut data);
+                thread::sleep(lobby.settings.tick);
+                if lobby.shutdown.started() {
+                    break;
+                }
+                let mut data = lock(&lobby.players);
+                lobby.expire_suspended(&mut data);
+                // go through some process of selecting a game
+                let game = Game::TicTacToe;
+                let id = lobby.next_id.load(Ordering::Relaxed);
+                let pair = Lobby::find_pair(&mut data, game, lobby.settings.rating_matchmaking, id);
                 if let Some(pair) = pair {
-                    // go through some process of selecting a game
-                    let players2 = Arc::clone(&players);
-                    thread::spawn(move|| {
-                        tic_tac_toe::begin(
-                            players2,
-                            Session::new(pair)
-                        );
+                    lobby.next_id.fetch_add(1, Ordering::Relaxed);
+                    lobby.start(id, pair, lobby.settings.tic_tac_toe, None);
+                } else if let Some(auto_bot) = lobby.settings.auto_bot {
+                    // bots left waiting, e.g. from a restored game, aren't given a bot to play
+                    let waited = data.iter().find(|player| {
+                        matches!(player.status, Status::Waiting(since) if since.elapsed() >= auto_bot.after) && player.bot.is_none()
                     });
+                    if let Some(addr) = waited.map(|player| player.addr) {
+                        log::info!("  {addr}  waited too long, playing a bot");
+                        lobby.seat_bot(&mut data, addr, auto_bot.difficulty);
+                    }
                 }
             }
         });
     }
 
+    /// Starts the game thread for a new session, or one carrying
+    /// on from a snapshot with the crosses player first in `pair`.
+    fn start(&self, id: u64, pair: (SocketAddr, SocketAddr), settings: GameSettings, resume: Option<Snapshot>) {
+        let players = Arc::clone(&self.players);
+        let session = Session::new(id, pair, settings, self.settings.tick, Arc::clone(&self.shutdown), Arc::clone(&self.live));
+        let db = self.db.clone();
+        thread::spawn(move|| {
+            let game = panic::catch_unwind(AssertUnwindSafe(|| {
+                tic_tac_toe::begin(
+                    Arc::clone(&players),
+                    session,
+                    db.clone(),
+                    resume,
+                );
+            }));
+            if game.is_err() {
+                Lobby::recover(&players, &db, id);
+            }
+        });
+    }
+
+    /// Puts the players of a game whose thread panicked back in the lobby,
+    /// so one broken game doesn't leave them stuck. Its snapshot is removed
+    /// as carrying on after a restart would likely panic again.
+    fn recover(players: &Mutex<Vec<Player>>, db: &Database, id: u64) {
+        log::error!("Game {id} stopped unexpectedly, returning its players to the lobby");
+        let mut data = lock(players);
+        for player in data.iter_mut() {
+            if player.status == Status::Playing(id) {
+                player.status = Status::Idle;
+                // also stops bots
+                let _ = Session::send(player, Message::GameOver(End::Aborted));
+            }
+            if player.spectating == Some(id) {
+                player.spectating = None;
+                let _ = Session::send(player, Message::GameOver(End::Aborted));
+            }
+        }
+        if let Err(e) = db.delete_snapshot(id) {
+            log::error!("Unable to delete the snapshot of game {id}. {e}");
+        }
+    }
+
+    /// Starts a game between the player at `addr` and a new bot.
+    fn seat_bot(&self, players: &mut Vec<Player>, addr: SocketAddr, difficulty: Difficulty) {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let mut bot = bot::spawn(difficulty);
+        bot.status = Status::Playing(id);
+        let pair = (addr, bot.addr);
+        players.push(bot);
+
+        if let Some(player) = players.iter_mut().find(|player| player.addr == addr) {
+            player.status = Status::Playing(id);
+            player.spectating = None;
+        }
+        self.start(id, pair, self.settings.tic_tac_toe, None);
+    }
+
+    /// Plays a bot straight away, also taking the player
+    /// at `addr` out of the matchmaking queue.
+    pub fn play_bot(&self, addr: SocketAddr, game: Game, difficulty: Difficulty) -> Result<(), String> {
+        let mut data = lock(&self.players);
+        match data.iter().find(|player| player.addr == addr) {
+            Some(player) if matches!(player.status, Status::Playing(_) | Status::Resuming(_)) => Err("Already playing a game".to_string()),
+            Some(_) if !self.settings.game(game).enabled => Err(format!("{} isn't played on this server", game.name())),
+            Some(_) if self.shutdown.started() => Err("The server is shutting down".to_string()),
+            Some(_) => {
+                // only one game to choose from for now
+                match game {
+                    Game::TicTacToe => self.seat_bot(&mut data, addr, difficulty),
+                }
+                Ok(())
+            },
+            None => Err("Not connected to the lobby".to_string()),
+        }
+    }
+
     // return two addrs for both players
-    fn find_pair(players: &mut [Player]) -> Option<(SocketAddr, SocketAddr)> {
-        let mut waiting: Vec<&mut Player> = players.iter_mut().filter(|player| player.status == Status::Waiting).collect();
+    // the player waiting the longest is always matched first, either
+    // with the next player or the one closest to their rating
+    fn find_pair(players: &mut [Player], game: Game, by_rating: bool, session: u64) -> Option<(SocketAddr, SocketAddr)> {
+        let mut waiting: Vec<&mut Player> = players
+            .iter_mut()
+            .filter(|player| matches!(player.status, Status::Waiting(_)) && player.bot.is_none())
+            .collect();
 
-        if waiting.len() < 2 { 
-            None
-        } else {
-            waiting[0].status = Status::Playing;
-            waiting[1].status = Status::Playing;
-            
-            Some((waiting[0].addr, waiting[1].addr))
+        for i in 0..waiting.len() {
+            let first = &waiting[i];
+            // never match an account against itself
+            let candidates = waiting.iter()
+                .enumerate()
+                .skip(i + 1)
+                .filter(|(_, other)| !first.same_account(other));
+
+            let second = if by_rating {
+                let rating = first.matchmaking_rating(game);
+                candidates
+                    .min_by(|(_, a), (_, b)| {
+                        let a = (a.matchmaking_rating(game) - rating).abs();
+                        let b = (b.matchmaking_rating(game) - rating).abs();
+                        a.total_cmp(&b)
+                    })
+                    .map(|(j, _)| j)
+            } else {
+                candidates.map(|(j, _)| j).next()
+            };
+
+            if let Some(j) = second {
+                for k in [i, j] {
+                    waiting[k].status = Status::Playing(session);
+                    waiting[k].spectating = None;
+                }
+
+                return Some((waiting[i].addr, waiting[j].addr));
+            }
         }
+
+        None
     }
 
     // maintains a list of active connections
@@ -133,44 +674,151 @@ impl Lobby {
     // finished TcpStream threads
     pub fn monitor(&self) {
         let players = Arc::clone(&self.players);
+        let Settings { tick, ping_interval, .. } = self.settings;
         thread::spawn(move|| {
-            let mut ping = common::PING_INTERVAL;
+            let mut pinged = Instant::now();
             loop {
-                thread::sleep(THREAD_SLEEP);
-                let mut data = players.lock().unwrap();
-
-                // count down from the ping interval in terms of thread sleeps
-                if ping > 0 {
-                    ping -= 1;
-                } else {
-                    ping = common::PING_INTERVAL;
+                thread::sleep(tick);
+                let mut data = lock(&players);
+
+                // print active connections only if connections change,
+                // removing them first so the status isn't sent to them
+                let initial_len = data.len();
+                data.retain(|player| !player.thread.is_finished());
+                if data.len() != initial_len { Lobby::print_connections(&data); }
+
+                if pinged.elapsed() >= ping_interval {
+                    pinged = Instant::now();
                     let status = ServerStatus { n_players: data.len() };
-                    let msg = common::tic_tac_toe::Message::Status(status);
+                    let msg = ServerMessage::Ping(status);
                     for player in data.iter() {
-                        Session::send(player, msg.clone()).unwrap();
+                        let _ = Session::send(player, msg.clone());
                     }
                 }
-                
-                // print active connections only if connections change
-                let initial_len = data.len();
-                data.retain(|player| !player.thread.is_finished());
-                if data.len() != initial_len { Lobby::print_connections(&data); }
             }
         });
     }
 
+    /// Whether the lobby has as many players as the server allows.
+    pub fn is_full(&self) -> bool {
+        self.settings.max_players.is_some_and(|max| lock(&self.players).len() >= max)
+    }
+
     pub fn add_and_print_connections(&self, new: Player) {
-        let mut data = self.players.lock().unwrap();
+        let mut data = lock(&self.players);
         Lobby::print_connections(&data);
-        println!("  {}  <--  new", new.addr);
+        log::info!("  {}  <--  new", new.addr);
         Lobby::add_connection(&mut data, new)
     }
 
-    fn print_connections(players: &Vec<Player>) {
-        println!("Active players:");
-        if players.is_empty() { println!("  None"); }
+    /// Adds the player at `addr` to the matchmaking queue.
+    pub fn find_game(&self, addr: SocketAddr, game: Game) -> Result<(), String> {
+        let mut data = lock(&self.players);
+        match data.iter_mut().find(|player| player.addr == addr) {
+            Some(_) if !self.settings.game(game).enabled => Err(format!("{} isn't played on this server", game.name())),
+            Some(_) if self.shutdown.started() => Err("The server is shutting down".to_string()),
+            Some(player) if matches!(player.status, Status::Playing(_) | Status::Resuming(_)) => Err("Already playing a game".to_string()),
+            Some(player) if player.status == Status::Idle => {
+                // only one game to choose from for now
+                match game {
+                    Game::TicTacToe => player.status = Status::Waiting(Instant::now()),
+                }
+                Ok(())
+            },
+            Some(_) => Err("Already looking for a game".to_string()),
+            None => Err("Not connected to the lobby".to_string()),
+        }
+    }
+
+    pub fn live_games(&self) -> Vec<LiveGame> {
+        lock(&self.live).clone()
+    }
+
+    /// Starts or stops the player at `addr` spectating a live game.
+    pub fn spectate(&self, addr: SocketAddr, id: Option<u64>) -> Result<(), String> {
+        let mut data = lock(&self.players);
+        // checked while holding the players lock so the
+        // game can't end before the spectator is added
+        if let Some(id) = id {
+            if !lock(&self.live).iter().any(|game| game.id == id) {
+                return Err(format!("No live game with id {id}"));
+            }
+        }
+        match data.iter_mut().find(|player| player.addr == addr) {
+            Some(player) if matches!(player.status, Status::Playing(_)) => Err("Can't spectate during your own game".to_string()),
+            Some(player) => {
+                player.spectating = id;
+                // through the game's channel so it arrives after
+                // any moves the game has already sent them
+                if id.is_none() {
+                    let _ = Session::send(player, ServerMessage::StoppedSpectating);
+                }
+                Ok(())
+            },
+            None => Err("Not connected to the lobby".to_string()),
+        }
+    }
+
+    /// Sends a chat message from the player at `addr` to everyone on the
+    /// channel, including the sender. `text` should already be filtered.
+    pub fn chat(&self, addr: SocketAddr, channel: ChatChannel, text: String) -> Result<(), String> {
+        let data = lock(&self.players);
+        let sender = data.iter().find(|player| player.addr == addr).ok_or("Not connected to the lobby")?;
+        let msg = ServerMessage::Chat(ChatMessage {
+            channel,
+            from: sender.name(),
+            text,
+        });
+
+        match channel {
+            ChatChannel::Lobby => Session::broadcast(data.iter(), msg),
+            ChatChannel::Game => {
+                let session = match (&sender.status, sender.spectating) {
+                    (&Status::Playing(id), _) | (_, Some(id)) => id,
+                    _ => return Err("Not playing or watching a game".to_string()),
+                };
+                let audience = data.iter().filter(|player| {
+                    player.status == Status::Playing(session) || player.spectating == Some(session)
+                });
+                Session::broadcast(audience, msg);
+            },
+        }
+        Ok(())
+    }
+
+    /// Changes the account used by the player at `addr`.
+    /// Accounts can't be changed in the middle of a game.
+    pub fn set_account(&self, addr: SocketAddr, account: Option<Account>) -> Result<(), String> {
+        let mut data = lock(&self.players);
+        let player = Self::account_player(&mut data, addr)?;
+        player.account = account;
+        log::info!("  {}  is now  {}", player.addr, player.name());
+        self.resume(&mut data, addr);
+        Ok(())
+    }
+
+    /// Fails for the same reasons [`Lobby::set_account`] would, so
+    /// requests can be refused before the database is changed.
+    pub fn can_set_account(&self, addr: SocketAddr) -> Result<(), String> {
+        Self::account_player(&mut lock(&self.players), addr).map(|_| ())
+    }
+
+    fn account_player(players: &mut [Player], addr: SocketAddr) -> Result<&mut Player, String> {
+        match players.iter_mut().find(|player| player.addr == addr) {
+            Some(player) if matches!(player.status, Status::Playing(_) | Status::Resuming(_)) => Err("Can't change accounts during a game".to_string()),
+            Some(player) => Ok(player),
+            None => Err("Not connected to the lobby".to_string()),
+        }
+    }
+
+    fn print_connections(players: &[Player]) {
+        log::info!("Active players:");
+        if players.is_empty() { log::info!("  None"); }
         for player in players.iter() {
-            println!("  {}", player.addr);
+            match &player.account {
+                Some(account) => log::info!("  {}  ({})", player.addr, account.username),
+                None => log::info!("  {}", player.addr),
+            }
         }
     }
 
@@ -0,0 +1,76 @@
+use std::{
+    thread,
+    net::SocketAddr,
+    sync::atomic::{AtomicU16, Ordering},
+    sync::mpsc::{channel, Receiver, Sender},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use common::{ChannelBuf, ai::{Ai, Difficulty}};
+use common::tic_tac_toe::{Board, End, Message, Offer, Piece};
+
+use super::Player;
+
+/// Pause before the bot moves so the game doesn't feel instant.
+const MOVE_DELAY: Duration = Duration::from_millis(500);
+
+/// Bots are given made up addresses on port numbers
+/// counting up from one, real players never connect
+/// from the unspecified address.
+static NEXT_PORT: AtomicU16 = AtomicU16::new(1);
+
+/// Creates a player that is moved by the computer from its own thread.
+/// Messages are passed through channels in the same way as they are
+/// for a connection, so the game can't tell the difference.
+pub fn spawn(difficulty: Difficulty) -> Player {
+    let addr = SocketAddr::from(([0, 0, 0, 0], NEXT_PORT.fetch_add(1, Ordering::Relaxed)));
+    let (tx_t, rx) = channel::<ChannelBuf>();
+    let (tx, rx_t) = channel::<ChannelBuf>();
+
+    let seed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_nanos() as u64;
+    let ai = Ai::new(difficulty, seed);
+    let thread = thread::spawn(move|| play(ai, tx_t, rx_t));
+
+    let mut player = Player::new(thread, addr, tx, rx);
+    player.bot = Some(difficulty);
+    player
+}
+
+/// Plays until the game is over and the session has ended. Draws
+/// and takebacks are always declined and rematches accepted.
+fn play(mut ai: Ai, tx: Sender<ChannelBuf>, rx: Receiver<ChannelBuf>) {
+    let send = |msg: Message| {
+        let _ = tx.send(common::Message::from(msg).into());
+    };
+    let mut board = Board::new(0);
+    let mut piece = Piece::Empty;
+
+    // the session is dropped when the game thread ends
+    while let Ok(msg) = rx.recv() {
+        let Ok(common::Message::TicTacToe(msg)) = common::Message::try_from(msg) else {
+            continue; // pings and chat
+        };
+        match msg {
+            Message::Preamble(state) => {
+                board = Board::new(state.board.size);
+                piece = state.piece;
+            },
+            Message::YourTurn(_) | Message::InvalidMove(_) => {
+                thread::sleep(MOVE_DELAY);
+                if let Some((x, y)) = ai.choose_move(&board, &piece) {
+                    send(Message::Move((piece.clone(), x, y)));
+                }
+            },
+            Message::Move((p, x, y)) => board.place(p, x, y),
+            Message::TakeBack(moves) => {
+                for (_, x, y) in moves {
+                    board.place(Piece::Empty, x, y);
+                }
+            },
+            Message::Offer(offer) => send(Message::Answer(offer, offer == Offer::Rematch)),
+            // no rematch, the game thread is finished with the bot
+            Message::Answer(Offer::Rematch, false) | Message::GameOver(End::Disconnect | End::Aborted) => return,
+            _ => (),
+        }
+    }
+}
@@ -0,0 +1,115 @@
+use std::time::{Duration, Instant};
+
+use common::TimeControl;
+use common::tic_tac_toe::{Clocks, Piece};
+
+/// Keeps both players' time during a game with a time control.
+pub struct Clock {
+    control: TimeControl,
+    /// Time left for crosses and noughts at the start of the turn.
+    remaining: [Duration; 2],
+    turn_started: Instant,
+}
+
+impl Clock {
+    pub fn new(control: TimeControl) -> Self {
+        Clock {
+            control,
+            remaining: [control.initial(); 2],
+            turn_started: Instant::now(),
+        }
+    }
+
+    /// Carries on with the time left from a snapshot.
+    pub fn resume(control: TimeControl, clocks: Clocks) -> Self {
+        Clock {
+            control,
+            remaining: [clocks.crosses, clocks.noughts],
+            turn_started: Instant::now(),
+        }
+    }
+
+    fn index(piece: &Piece) -> usize {
+        match piece {
+            Piece::Nought => 1,
+            _ => 0,
+        }
+    }
+
+    pub fn start_turn(&mut self) {
+        self.turn_started = Instant::now();
+    }
+
+    /// Whether the player with `piece` has run out of time this turn.
+    pub fn expired(&self, piece: &Piece) -> bool {
+        self.turn_started.elapsed() > self.remaining[Clock::index(piece)]
+    }
+
+    /// Takes the time used this turn off the player's clock. Per move
+    /// limits start afresh each turn so there is nothing to take off.
+    pub fn end_turn(&mut self, piece: &Piece) {
+        if let TimeControl::Fischer { increment, .. } = self.control {
+            let remaining = &mut self.remaining[Clock::index(piece)];
+            *remaining = remaining.saturating_sub(self.turn_started.elapsed()) + increment;
+        }
+    }
+
+    pub fn clocks(&self) -> Clocks {
+        Clocks {
+            crosses: self.remaining[0],
+            noughts: self.remaining[1],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn parses_time_controls() {
+        assert_eq!("5+3".parse(), Ok(TimeControl::Fischer { initial: Duration::from_secs(300), increment: Duration::from_secs(3) }));
+        assert_eq!("1+0".parse(), Ok(TimeControl::Fischer { initial: Duration::from_secs(60), increment: Duration::ZERO }));
+        assert_eq!("30s".parse(), Ok(TimeControl::PerMove(Duration::from_secs(30))));
+        assert_eq!("10 + 5".parse(), Ok(TimeControl::Fischer { initial: Duration::from_secs(600), increment: Duration::from_secs(5) }));
+    }
+
+    #[test]
+    fn rejects_malformed_time_controls() {
+        for control in ["", "5", "5+", "+3", "5+3+1", "a+3", "-5+3", "5.5+3", "s", "0s", "0+5", "30m"] {
+            assert!(control.parse::<TimeControl>().is_err(), "accepted {control}");
+        }
+    }
+
+    #[test]
+    fn adds_the_increment_after_a_move() {
+        let mut clock = Clock::new("1+3".parse().unwrap());
+        clock.start_turn();
+        clock.end_turn(&Piece::Nought);
+
+        let clocks = clock.clocks();
+        assert_eq!(clocks.crosses, Duration::from_secs(60));
+        assert!(clocks.noughts > Duration::from_secs(62) && clocks.noughts <= Duration::from_secs(63), "{:?}", clocks.noughts);
+    }
+
+    #[test]
+    fn per_move_limits_start_afresh() {
+        let mut clock = Clock::new("30s".parse().unwrap());
+        clock.start_turn();
+        thread::sleep(Duration::from_millis(10));
+        clock.end_turn(&Piece::Cross);
+        assert_eq!(clock.clocks().crosses, Duration::from_secs(30));
+    }
+
+    #[test]
+    fn expires_once_the_time_runs_out() {
+        let clocks = Clocks { crosses: Duration::from_millis(20), noughts: Duration::from_secs(60) };
+        let mut clock = Clock::resume("1+0".parse().unwrap(), clocks);
+        clock.start_turn();
+        assert!(!clock.expired(&Piece::Cross));
+        thread::sleep(Duration::from_millis(40));
+        assert!(clock.expired(&Piece::Cross));
+        assert!(!clock.expired(&Piece::Nought));
+    }
+}
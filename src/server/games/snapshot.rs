@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+use common::{Game, TimeControl};
+use common::ai::Difficulty;
+use common::tic_tac_toe::{Clocks, Move, Piece};
+
+/// Who sits in a seat of a saved game, guests
+/// can't be recognised again so aren't saved.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Seat {
+    Account(i64),
+    /// Bots are started again when the game is restored.
+    Bot(Difficulty),
+}
+
+/// Everything needed to carry on a game after the server restarts.
+/// Saved to the database after every move and removed once it ends.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Snapshot {
+    /// Session id, kept so the game has the same id after a restart.
+    pub id: u64,
+    pub game: Game,
+    pub board_size: usize,
+    pub time_control: Option<TimeControl>,
+    /// Seats and names in the order they move, crosses first.
+    pub seats: [Seat; 2],
+    pub names: [String; 2],
+    /// Moves played so far, which rebuild the board.
+    pub moves: Vec<Move>,
+    /// Time left at the start of the turn being played. Time
+    /// spent on the turn before the server stopped is given back.
+    pub clocks: Option<Clocks>,
+    /// When the game started, in unix seconds.
+    pub started: u64,
+}
+
+impl Snapshot {
+    /// Moves alternate starting with crosses, including after takebacks.
+    pub fn current_player(&self) -> Piece {
+        if self.moves.len().is_multiple_of(2) { Piece::Cross } else { Piece::Nought }
+    }
+}
@@ -1,11 +1,19 @@
 use std::{
     thread,
+    net::SocketAddr,
     sync::{Arc, Mutex},
+    time::{Duration, Instant},
 };
 
-use crate::games::{Player, Session};
+use crate::error::lock;
+use crate::games::{Player, Session, Snapshot};
+use crate::games::clock::Clock;
+use crate::accounts::Account;
+use crate::database::Database;
+use crate::history::Recorder;
+use crate::ratings;
 
-use common::THREAD_SLEEP;
+use common::{Game, ServerMessage};
 use common::tic_tac_toe::{
     self,
     Message,
@@ -14,26 +22,94 @@ use common::tic_tac_toe::{
     Turn,
     ClientState,
     End,
+    Offer,
+    SpectatorState,
 };
 
-pub fn begin(players: Arc<Mutex<Vec<Player>>>, mut session: super::Session) {
-    session.game = Some(super::Game::TicTacToe);
+const GAME: Game = Game::TicTacToe;
 
-    let mut state = ServerState::new(tic_tac_toe::BOARD_SIZE);
+/// How long players have to agree to a rematch
+/// before they are returned to the lobby.
+const REMATCH_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Runs the session until the players stop playing rematches,
+/// carrying on from `resume` first if it was restored after a restart.
+pub fn begin(players: Arc<Mutex<Vec<Player>>>, mut session: super::Session, db: Database, mut resume: Option<Snapshot>) {
+    session.game = Some(GAME);
+
+    // keep playing while both players want a rematch,
+    // swapping who goes first each time
+    let mut swap = false;
+    while play(&players, &session, &db, &mut swap, resume.take()) && rematch(&players, &session) {
+        swap = !swap;
+    }
+}
+
+/// Plays a single game, returning whether it finished with both players
+/// still connected. `swap` is set to the seating of a resumed game.
+fn play(lobby: &Arc<Mutex<Vec<Player>>>, session: &Session, db: &Database, swap: &mut bool, resume: Option<Snapshot>) -> bool {
+    let mut state = ServerState::new(session.board_size);
+    if *swap {
+        std::mem::swap(&mut state.crosses_player, &mut state.noughts_player);
+    }
+    // accounts of the crosses and noughts players
+    // kept for rating the game even if one disconnects
+    let mut accounts: [Option<Account>; 2] = [None, None];
+    let mut crosses_addr = None;
+    let mut recorder = None;
+    // spectators who have been sent the game so far
+    let mut watching: Vec<SocketAddr> = Vec::new();
+    let mut clock = session.time_control.map(Clock::new);
+    // offer waiting for an answer and the piece of the player who made it
+    let mut offer: Option<(Offer, Piece)> = None;
+    // saved after every move if the game can be carried on after a restart
+    let mut snapshot: Option<Snapshot> = None;
+
+    if let Some(resume) = &resume {
+        for (p, x, y) in resume.moves.iter().cloned() {
+            state.board.place(p, x, y);
+        }
+        state.current_player = resume.current_player();
+        if let (Some(control), Some(clocks)) = (session.time_control, resume.clocks) {
+            clock = Some(Clock::resume(control, clocks));
+        }
+    }
     
-    println!("Started {:?} with {} and {}", tic_tac_toe::NAME, session.player1, session.player2);
+    log::info!("Started {:?} with {} and {}", tic_tac_toe::NAME, session.player1, session.player2);
     
     loop {
-        thread::sleep(THREAD_SLEEP);
-        let mut data = players.lock().unwrap();
+        thread::sleep(session.tick);
+        // saved once the lock is released so other
+        // games and the lobby don't wait on the disk
+        let mut writes = Vec::new();
+        let mut data = lock(lobby);
         // check that both players are still connected
-        let mut players: Vec<&mut Player> = data
-            .iter_mut()
-            .filter(|p| p.addr == session.player1 || p.addr == session.player2)
-            .collect();
+        let (mut players, mut spectators) = session.audience(&mut data);
         
-        match players.len() {
+        let finished = match players.len() {
             2 => {
+                update_spectators(session, &state, clock.as_ref(), &players, &spectators, &mut watching);
+
+                if session.shutdown.expired() && !matches!(state.turn, Turn::Begin | Turn::End) {
+                    if snapshot.is_some() {
+                        // the snapshot is up to date, carry on after the restart
+                        log::info!("Saved game {} to carry on after the restart", session.id);
+                        release_spectators(session, &mut spectators, End::Aborted);
+                        for player in players.iter_mut() {
+                            player.status = super::Status::Idle;
+                            // bots are started again with the game, stop this one
+                            if player.bot.is_some() {
+                                let _ = Session::send(player, Message::GameOver(End::Aborted));
+                            } else {
+                                let _ = Session::send(player, ServerMessage::Suspended(GAME));
+                            }
+                        }
+                        return false;
+                    }
+                    state.winner = End::Aborted;
+                    state.turn = Turn::End;
+                }
+
                 let current_player;
                 let next_player;
                 if state.current_player == Piece::Cross {
@@ -46,62 +122,392 @@ pub fn begin(players: Arc<Mutex<Vec<Player>>>, mut session: super::Session) {
 
                 match state.turn {
                     Turn::Begin => {
-                        let config1 = ClientState::new(next_player.addr.to_string(), Piece::Cross, state.board.size);
-                        let config2 = ClientState::new(current_player.addr.to_string(), Piece::Nought, state.board.size);
-                        Session::send(current_player, Message::Preamble(config1)).unwrap();
-                        Session::send(next_player, Message::Preamble(config2)).unwrap();
-                        println!("Found {} and {}", current_player.addr, next_player.addr);
+                        if resume.is_some() {
+                            // seat the players as they were, the lobby order may have changed
+                            state.crosses_player = players.iter().position(|player| player.addr == session.player1).unwrap_or(0);
+                            state.noughts_player = 1 - state.crosses_player;
+                            *swap = state.crosses_player == 1;
+                        }
+                        let crosses = &players[state.crosses_player];
+                        let noughts = &players[state.noughts_player];
+                        let mut config1 = ClientState::new(noughts.name(), Piece::Cross, state.board.size);
+                        config1.rating = crosses.rating(GAME);
+                        config1.opponent_rating = noughts.rating(GAME);
+                        config1.time_control = session.time_control;
+                        let mut config2 = ClientState::new(crosses.name(), Piece::Nought, state.board.size);
+                        config2.rating = noughts.rating(GAME);
+                        config2.opponent_rating = crosses.rating(GAME);
+                        config2.time_control = session.time_control;
+                        accounts = [crosses.account.clone(), noughts.account.clone()];
+                        crosses_addr = Some(crosses.addr);
+                        let variant = tic_tac_toe::variant(state.board.size);
+                        recorder = Some(match &resume {
+                            Some(resume) => Recorder::resume(resume),
+                            None => Recorder::new(GAME, variant, [crosses, noughts]),
+                        });
+                        session.register([crosses.name(), noughts.name()]);
+                        let _ = Session::send(crosses, Message::Preamble(config1));
+                        let _ = Session::send(noughts, Message::Preamble(config2));
+                        if let Some(resume) = &resume {
+                            for m in &resume.moves {
+                                Session::broadcast([&**crosses, &**noughts], Message::Move(m.clone()));
+                            }
+                            log::info!("Resumed {} and {}", crosses.name(), noughts.name());
+                        } else {
+                            log::info!("Found {} and {}", crosses.name(), noughts.name());
+                        }
+                        // guests can't be recognised after a restart so their games aren't saved
+                        if let (Some(first), Some(second)) = (crosses.seat(), noughts.seat()) {
+                            snapshot = Some(Snapshot {
+                                id: session.id,
+                                game: GAME,
+                                board_size: state.board.size,
+                                time_control: session.time_control,
+                                seats: [first, second],
+                                names: [crosses.name(), noughts.name()],
+                                moves: Vec::new(),
+                                clocks: None,
+                                started: recorder.as_ref().map_or(0, Recorder::started),
+                            });
+                            checkpoint(&mut writes, &mut snapshot, recorder.as_ref(), clock.as_ref());
+                        }
                         state.turn = Turn::TurnStart;
+                        None
                     },
                     Turn::TurnStart => {
-                        Session::send(current_player, Message::YourTurn).unwrap();
-                        Session::send(next_player, Message::WaitTurn).unwrap();
+                        if let Some(clock) = &mut clock {
+                            clock.start_turn();
+                        }
+                        let clocks = clock.as_ref().map(Clock::clocks);
+                        let _ = Session::send(current_player, Message::YourTurn(clocks));
+                        Session::broadcast([&**next_player].into_iter().chain(spectators.iter().map(|p| &**p)), Message::WaitTurn(clocks));
                         state.turn = Turn::TurnWait;
+                        None
+                    },
+                    Turn::TurnWait if clock.as_ref().is_some_and(|clock| clock.expired(&state.current_player)) => {
+                        state.winner = End::Timeout(state.current_player.clone());
+                        state.turn = Turn::End;
+                        None
                     },
                     Turn::TurnWait => {
-                        match super::try_recv(current_player) {
-                            Ok(Message::Move((_, x, y))) => {
-                                match state.board.try_place(dbg!(state.current_player.clone()), x, y) {
-                                    Ok(m) => {
-                                        Session::broadcast(current_player, next_player, Message::Move(m)).unwrap();
-                                        match state.board.check_victory(state.current_player.clone()) {
-                                            Some(end) => {
-                                                state.winner = end;
-                                                state.turn = Turn::End;
-                                            },
-                                            None => {
-                                                state.turn = Turn::TurnStart;
-                                                state.current_player = state.current_player.next();
-                                            },
-                                        }
+                        // the player to move goes first, either player
+                        // can resign or make and answer offers
+                        for piece in [state.current_player.clone(), state.current_player.clone().next()] {
+                            let (player, opponent) = if piece == state.current_player {
+                                (current_player, next_player)
+                            } else {
+                                (next_player, current_player)
+                            };
+
+                            match super::try_recv(player) {
+                                Ok(Message::Move((_, x, y))) if piece == state.current_player => {
+                                    match state.board.try_place(state.current_player.clone(), x, y) {
+                                        Ok(m) => {
+                                            if let Some(recorder) = &mut recorder {
+                                                recorder.push(m.clone());
+                                            }
+                                            if let Some(clock) = &mut clock {
+                                                clock.end_turn(&state.current_player);
+                                            }
+                                            offer = None;
+                                            Session::broadcast(players.iter().chain(&spectators).map(|p| &**p), Message::Move(m));
+                                            match state.board.check_victory(state.current_player.clone()) {
+                                                Some(end) => {
+                                                    state.winner = end;
+                                                    state.turn = Turn::End;
+                                                },
+                                                None => {
+                                                    state.turn = Turn::TurnStart;
+                                                    state.current_player = state.current_player.next();
+                                                    checkpoint(&mut writes, &mut snapshot, recorder.as_ref(), clock.as_ref());
+                                                },
+                                            }
+                                        },
+                                        Err(e) => {
+                                            let _ = Session::send(current_player, Message::InvalidMove(e));
+                                        },
+                                    }
+                                },
+                                Ok(Message::Move(_)) => error(player, "It isn't your turn"),
+                                Ok(Message::Resign) => {
+                                    state.winner = End::Resignation(piece);
+                                    state.turn = Turn::End;
+                                },
+                                Ok(Message::Offer(Offer::Rematch)) => error(player, "The game isn't over yet"),
+                                Ok(Message::Offer(_)) if offer.is_some() => error(player, "There is already an offer waiting for an answer"),
+                                Ok(Message::Offer(Offer::Takeback)) if takeback_len(&state, &piece, recorder.as_ref()) == 0 => {
+                                    error(player, "You have no moves to take back");
+                                },
+                                Ok(Message::Offer(o)) => {
+                                    offer = Some((o, piece));
+                                    let _ = Session::send(opponent, Message::Offer(o));
+                                },
+                                Ok(Message::Answer(o, accepted)) => match offer.take() {
+                                    Some((pending, from)) if pending == o && from != piece => match o {
+                                        _ if !accepted => {
+                                            let _ = Session::send(opponent, Message::Answer(o, false));
+                                        },
+                                        Offer::Draw => {
+                                            state.winner = End::AgreedDraw;
+                                            state.turn = Turn::End;
+                                        },
+                                        Offer::Takeback => {
+                                            let n = takeback_len(&state, &from, recorder.as_ref());
+                                            let taken: Vec<_> = recorder.as_mut().map(|r| (0..n).filter_map(|_| r.pop()).collect()).unwrap_or_default();
+                                            for (p, x, y) in taken.iter().cloned() {
+                                                state.board.place(Piece::Empty, x, y);
+                                                state.current_player = p;
+                                            }
+                                            Session::broadcast(players.iter().chain(&spectators).map(|p| &**p), Message::TakeBack(taken));
+                                            state.turn = Turn::TurnStart;
+                                            checkpoint(&mut writes, &mut snapshot, recorder.as_ref(), clock.as_ref());
+                                        },
+                                        // never accepted as an offer during a game
+                                        Offer::Rematch => error(player, "The game isn't over yet"),
+                                    },
+                                    pending => {
+                                        offer = pending;
+                                        error(player, "There is no offer to answer");
                                     },
-                                    Err(e) => Session::send(current_player, Message::InvalidMove(e)).unwrap(),
-                                }
-                            },
-                            Ok(m) => Session::send(current_player, Message::InvalidMove(format!("Wrong message type {m:?}"))).unwrap(),
-                            Err(_) => (), // nothing received
+                                },
+                                Ok(m) => {
+                                    let _ = Session::send(player, Message::InvalidMove(format!("Wrong message type {m:?}")));
+                                },
+                                Err(_) => (), // nothing received
+                            }
+
+                            if state.turn != Turn::TurnWait {
+                                break;
+                            }
                         }
+                        None
                     },
                     Turn::End => {
-                        Session::broadcast(current_player, next_player, Message::GameOver(state.winner.clone())).unwrap();
-                        players[0].status = super::Status::Waiting;
-                        players[1].status = super::Status::Waiting;
-                        println!("Game over, winner: {:?}", state.winner);
-                        break;
+                        Session::broadcast(players.iter().map(|p| &**p), Message::GameOver(state.winner.clone()));
+                        let score = match state.winner {
+                            End::Victory(Piece::Cross) | End::Timeout(Piece::Nought) | End::Resignation(Piece::Nought) => Some(1.0),
+                            End::Victory(_) | End::Timeout(_) | End::Resignation(_) => Some(0.0),
+                            End::Draw | End::AgreedDraw => Some(0.5),
+                            End::Disconnect | End::Aborted => None,
+                        };
+                        if let Some(score) = score {
+                            rate(&mut writes, &accounts, score);
+                        }
+                        if let Some(recorder) = recorder.take() {
+                            writes.push(Write::Save(recorder, state.winner.clone()));
+                        }
+                        discard(&mut writes, snapshot.take());
+                        release_spectators(session, &mut spectators, state.winner.clone());
+                        log::info!("Game over, winner: {:?}", state.winner);
+                        // both players stay in the session until
+                        // they have decided whether to play again
+                        Some(true)
                     }
                 }
             },
             1 => {
-                let player = &mut players[0];
-                println!("One player dropped");
-                Session::send(player, Message::GameOver(End::Disconnect)).unwrap();
-                player.status = super::Status::Waiting;
-                break;
+                log::info!("One player dropped");
+                let _ = Session::send(players[0], Message::GameOver(End::Disconnect));
+                // leaving a started game counts as a loss
+                if let Some(crosses_addr) = crosses_addr {
+                    let score = if players[0].addr == crosses_addr { 1.0 } else { 0.0 };
+                    rate(&mut writes, &accounts, score);
+                }
+                if let Some(recorder) = recorder.take() {
+                    writes.push(Write::Save(recorder, End::Disconnect));
+                }
+                discard(&mut writes, snapshot.take());
+                players[0].status = super::Status::Waiting(Instant::now());
+                release_spectators(session, &mut spectators, End::Disconnect);
+                Some(false)
             },
             _ => {
-                println!("Both players dropped");
+                log::info!("Both players dropped");
+                release_spectators(session, &mut spectators, End::Disconnect);
+                if let Some(recorder) = recorder.take() {
+                    writes.push(Write::Save(recorder, End::Disconnect));
+                }
+                discard(&mut writes, snapshot.take());
+                Some(false)
+            }
+        };
+
+        drop(data);
+        write(db, lobby, session, writes);
+        if let Some(finished) = finished {
+            return finished;
+        }
+    }
+}
+
+/// Waits for one player to offer a rematch and the other to accept. If either
+/// declines, disconnects or the time runs out, both go back to the lobby.
+fn rematch(players: &Arc<Mutex<Vec<Player>>>, session: &Session) -> bool {
+    let deadline = Instant::now() + REMATCH_TIMEOUT;
+    // address of the player who offered first
+    let mut offered: Option<SocketAddr> = None;
+
+    loop {
+        thread::sleep(session.tick);
+        let mut data = lock(players);
+        let (mut players, _) = session.audience(&mut data);
+
+        let mut answer = None;
+        // no new games once the server is shutting down
+        if players.len() < 2 || Instant::now() > deadline || session.shutdown.started() {
+            answer = Some(false);
+        }
+        for i in 0..players.len() {
+            if answer.is_some() {
                 break;
             }
+            let (player, opponent) = (&players[i], &players[players.len() - 1 - i]);
+            match super::try_recv(player) {
+                // offering back counts as accepting
+                Ok(Message::Offer(Offer::Rematch) | Message::Answer(Offer::Rematch, true)) if offered.is_some_and(|addr| addr != player.addr) => {
+                    answer = Some(true);
+                },
+                Ok(Message::Offer(Offer::Rematch)) if offered.is_none() => {
+                    offered = Some(player.addr);
+                    let _ = Session::send(opponent, Message::Offer(Offer::Rematch));
+                },
+                Ok(Message::Answer(Offer::Rematch, false)) => answer = Some(false),
+                Ok(_) => error(player, "The game is over"),
+                Err(_) => (), // nothing received
+            }
+        }
+
+        match answer {
+            Some(true) => return true,
+            Some(false) => {
+                for player in players.iter_mut() {
+                    player.status = super::Status::Waiting(Instant::now());
+                    let _ = Session::send(player, Message::Answer(Offer::Rematch, false));
+                }
+                return false;
+            },
+            None => (),
+        }
+    }
+}
+
+/// Number of moves undone if the player with `piece` takes back their last
+/// move. Their opponent's reply is undone too if it is their turn again.
+fn takeback_len(state: &ServerState, piece: &Piece, recorder: Option<&Recorder>) -> usize {
+    let n = if *piece == state.current_player { 2 } else { 1 };
+    match recorder {
+        Some(recorder) if recorder.len() >= n => n,
+        _ => 0,
+    }
+}
+
+/// Tells a player why their request was refused
+/// without asking them to move again.
+fn error(player: &Player, e: &str) {
+    let _ = Session::send(player, ServerMessage::Error(e.to_string()));
+}
+
+/// Sends the game so far to anyone who has just started spectating
+/// and tells everyone watching or playing when the audience changes.
+fn update_spectators(session: &Session, state: &ServerState, clock: Option<&Clock>, players: &[&mut Player], spectators: &[&mut Player], watching: &mut Vec<SocketAddr>) {
+    let crosses = &players[state.crosses_player];
+    let noughts = &players[state.noughts_player];
+    let clocks = clock.map(Clock::clocks);
+    for spectator in spectators.iter().filter(|s| !watching.contains(&s.addr)) {
+        let spectate = SpectatorState {
+            board: state.board.clone(),
+            players: [crosses.name(), noughts.name()],
+            ratings: [crosses.rating(GAME), noughts.rating(GAME)],
+            current_player: state.current_player.clone(),
+            spectators: spectators.len(),
+            clocks,
+        };
+        Session::broadcast([&**spectator], Message::Spectate(spectate));
+    }
+
+    if spectators.len() != watching.len() {
+        session.set_spectators(spectators.len());
+        Session::broadcast(players.iter().chain(spectators).map(|p| &**p), Message::Spectators(spectators.len()));
+    }
+    *watching = spectators.iter().map(|s| s.addr).collect();
+}
+
+/// Saves the game so far so it can carry on after a restart.
+fn checkpoint(writes: &mut Vec<Write>, snapshot: &mut Option<Snapshot>, recorder: Option<&Recorder>, clock: Option<&Clock>) {
+    let Some(snapshot) = snapshot else { return };
+    snapshot.moves = recorder.map(|r| r.moves().to_vec()).unwrap_or_default();
+    snapshot.clocks = clock.map(Clock::clocks);
+    writes.push(Write::Checkpoint(snapshot.clone()));
+}
+
+/// Removes the snapshot of a game that won't be carried on.
+fn discard(writes: &mut Vec<Write>, snapshot: Option<Snapshot>) {
+    if let Some(snapshot) = snapshot {
+        writes.push(Write::Discard(snapshot.id));
+    }
+}
+
+/// Ends the game for its spectators, who are
+/// then free to watch another game.
+fn release_spectators(session: &Session, spectators: &mut [&mut Player], end: End) {
+    session.unregister();
+    Session::broadcast(spectators.iter().map(|p| &**p), Message::GameOver(end));
+    for spectator in spectators.iter_mut() {
+        spectator.spectating = None;
+    }
+}
+
+/// Rates the game if both seats were taken by different accounts.
+/// `score` is from the crosses player's perspective.
+fn rate(writes: &mut Vec<Write>, accounts: &[Option<Account>; 2], score: f64) {
+    let [Some(crosses), Some(noughts)] = accounts else { return };
+    if crosses.id == noughts.id { return; }
+    writes.push(Write::Rate([crosses.clone(), noughts.clone()], score));
+}
+
+/// Database changes made during a turn of the game.
+enum Write {
+    Checkpoint(Snapshot),
+    /// Removes the snapshot of the game with this id.
+    Discard(u64),
+    Save(Recorder, End),
+    /// Accounts of the crosses and noughts players with
+    /// the score from the crosses player's perspective.
+    Rate([Account; 2], f64),
+}
+
+/// Makes the changes from a turn once the players lock has been released.
+/// Players who are still connected after a rated game have their cached
+/// account updated and are sent their new rating.
+fn write(db: &Database, lobby: &Mutex<Vec<Player>>, session: &Session, writes: Vec<Write>) {
+    for write in writes {
+        match write {
+            Write::Checkpoint(snapshot) => {
+                if let Err(e) = db.save_snapshot(&snapshot) {
+                    log::error!("Unable to save game {}. {e}", snapshot.id);
+                }
+            },
+            Write::Discard(id) => {
+                if let Err(e) = db.delete_snapshot(id) {
+                    log::error!("Unable to remove the snapshot of game {id}. {e}");
+                }
+            },
+            Write::Save(recorder, end) => recorder.save(db, end),
+            Write::Rate([mut crosses, mut noughts], score) => match ratings::update(db, GAME, &mut crosses, &mut noughts, score) {
+                Ok(changes) => {
+                    let mut data = lock(lobby);
+                    for (account, change) in [crosses, noughts].into_iter().zip(changes) {
+                        let player = data.iter_mut().find(|player| {
+                            session.is_player(player) && player.account.as_ref().map(|a| a.id) == Some(account.id)
+                        });
+                        if let Some(player) = player {
+                            player.account = Some(account);
+                            let _ = Session::send(player, change);
+                        }
+                    }
+                },
+                Err(e) => log::error!("Unable to save ratings. {e}"),
+            },
         }
     }
 }
@@ -0,0 +1,92 @@
+use common::Game;
+use common::history::{GameRecord, GameSummary};
+use common::tic_tac_toe::{self, End, Move};
+
+use crate::database::{self, Database};
+use crate::games::{Player, Seat, Snapshot};
+
+/// Number of games returned when listing the history.
+pub const HISTORY_SIZE: usize = 50;
+
+/// Collects the moves of a game in progress
+/// so that it can be saved once it has finished.
+pub struct Recorder {
+    record: GameRecord,
+    accounts: [Option<i64>; 2],
+}
+
+impl Recorder {
+    /// Starts recording a game. The players are
+    /// given in the order that they move.
+    pub fn new(game: Game, variant: String, players: [&Player; 2]) -> Self {
+        Recorder {
+            record: GameRecord {
+                summary: GameSummary {
+                    id: 0, // assigned when saved
+                    game,
+                    variant,
+                    players: players.map(|player| player.name()),
+                    started: database::now(),
+                    ended: 0,
+                    result: End::Disconnect,
+                },
+                moves: Vec::new(),
+            },
+            accounts: players.map(|player| player.account().map(|account| account.id)),
+        }
+    }
+
+    /// Carries on recording a game restored after a restart.
+    pub fn resume(snapshot: &Snapshot) -> Self {
+        Recorder {
+            record: GameRecord {
+                summary: GameSummary {
+                    id: 0,
+                    game: snapshot.game,
+                    variant: tic_tac_toe::variant(snapshot.board_size),
+                    players: snapshot.names.clone(),
+                    started: snapshot.started,
+                    ended: 0,
+                    result: End::Disconnect,
+                },
+                moves: snapshot.moves.clone(),
+            },
+            accounts: snapshot.seats.map(|seat| match seat {
+                Seat::Account(id) => Some(id),
+                Seat::Bot(_) => None,
+            }),
+        }
+    }
+
+    pub fn push(&mut self, m: Move) {
+        self.record.moves.push(m);
+    }
+
+    pub fn pop(&mut self) -> Option<Move> {
+        self.record.moves.pop()
+    }
+
+    pub fn len(&self) -> usize {
+        self.record.moves.len()
+    }
+
+    pub fn moves(&self) -> &[Move] {
+        &self.record.moves
+    }
+
+    /// When the game started, in unix seconds.
+    pub fn started(&self) -> u64 {
+        self.record.summary.started
+    }
+
+    /// Saves the finished game to the database.
+    pub fn save(mut self, db: &Database, result: End) {
+        self.record.summary.ended = database::now();
+        self.record.summary.result = result;
+
+        match db.save_game(&self.record, self.accounts) {
+            Ok(id) => log::info!("Saved game {id}"),
+            Err(e) => log::error!("Unable to save game. {e}"),
+        }
+    }
+}
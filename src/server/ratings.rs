@@ -0,0 +1,135 @@
+use common::{Game, Ranking, ServerMessage};
+
+use crate::accounts::Account;
+use crate::database::Database;
+
+/// Rating given to accounts before their first rated game.
+const DEFAULT_RATING: f64 = 1200.0;
+
+/// New accounts move faster until they have played
+/// enough games for their rating to settle.
+const PROVISIONAL_GAMES: u32 = 20;
+const K_PROVISIONAL: f64 = 40.0;
+const K: f64 = 20.0;
+
+/// Number of accounts returned in a leaderboard.
+const LEADERBOARD_SIZE: usize = 20;
+
+/// Elo rating of an account for a single game.
+#[derive(Clone, Copy, Debug)]
+pub struct Rating {
+    pub rating: f64,
+    pub games: u32,
+}
+
+impl Default for Rating {
+    fn default() -> Self {
+        Rating {
+            rating: DEFAULT_RATING,
+            games: 0,
+        }
+    }
+}
+
+impl Rating {
+    pub fn display(&self) -> u32 {
+        self.rating.round() as u32
+    }
+
+    /// Rating after a game against `opponent`. The score is
+    /// 1 for a win, 0.5 for a draw and 0 for a loss.
+    fn after(self, opponent: Rating, score: f64) -> Rating {
+        let expected = 1.0 / (1.0 + 10f64.powf((opponent.rating - self.rating) / 400.0));
+        let k = if self.games < PROVISIONAL_GAMES { K_PROVISIONAL } else { K };
+
+        Rating {
+            rating: self.rating + k * (score - expected),
+            games: self.games + 1,
+        }
+    }
+}
+
+/// Loads the ratings of every game into the account.
+pub fn load(db: &Database, account: &mut Account) -> rusqlite::Result<()> {
+    for game in Game::ALL {
+        if let Some(rating) = db.rating(account, game)? {
+            account.ratings.insert(game, rating);
+        }
+    }
+    Ok(())
+}
+
+/// Updates and saves the ratings after a game between two
+/// accounts. `score` is from the perspective of `a`. The new ratings
+/// are worked out from the ones saved in the database, which may have
+/// changed since the game started. The cached ratings of both accounts
+/// are updated too, returning the messages to tell each player their change.
+pub fn update(db: &Database, game: Game, a: &mut Account, b: &mut Account, score: f64) -> rusqlite::Result<[ServerMessage; 2]> {
+    let ([old_a, old_b], [new_a, new_b]) = db.update_ratings(game, [a, b], |[old_a, old_b]| {
+        [old_a.after(old_b, score), old_b.after(old_a, 1.0 - score)]
+    })?;
+    a.ratings.insert(game, new_a);
+    b.ratings.insert(game, new_b);
+
+    log::info!("Rated {}: {} {} -> {}, {} {} -> {}",
+        game.name(),
+        a.username, old_a.display(), new_a.display(),
+        b.username, old_b.display(), new_b.display(),
+    );
+    Ok([change(game, old_a, new_a), change(game, old_b, new_b)])
+}
+
+fn change(game: Game, old: Rating, new: Rating) -> ServerMessage {
+    ServerMessage::RatingChange {
+        game,
+        rating: new.display(),
+        change: new.display() as i32 - old.display() as i32,
+    }
+}
+
+pub fn leaderboard(db: &Database, game: Game) -> rusqlite::Result<Vec<Ranking>> {
+    db.leaderboard(game, LEADERBOARD_SIZE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rating(rating: f64, games: u32) -> Rating {
+        Rating { rating, games }
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 1e-9, "expected {expected}, got {actual}");
+    }
+
+    #[test]
+    fn moves_slower_after_the_provisional_games() {
+        let opponent = rating(1500.0, 50);
+        // the last provisional game still uses the larger k
+        assert_close(rating(1500.0, PROVISIONAL_GAMES - 1).after(opponent, 1.0).rating, 1500.0 + K_PROVISIONAL / 2.0);
+        assert_close(rating(1500.0, PROVISIONAL_GAMES).after(opponent, 1.0).rating, 1500.0 + K / 2.0);
+        assert_eq!(rating(1500.0, PROVISIONAL_GAMES).after(opponent, 1.0).games, PROVISIONAL_GAMES + 1);
+    }
+
+    #[test]
+    fn equal_ratings_gain_and_lose_the_same() {
+        let a = rating(1400.0, 30);
+        let b = rating(1400.0, 30);
+        let winner = a.after(b, 1.0).rating - a.rating;
+        let loser = b.after(a, 0.0).rating - b.rating;
+        assert_close(winner, K / 2.0);
+        assert_close(loser, -K / 2.0);
+    }
+
+    #[test]
+    fn draws_move_ratings_towards_each_other() {
+        let stronger = rating(1600.0, 30);
+        let weaker = rating(1200.0, 30);
+        // 400 points apart the stronger player is expected to score 10/11
+        assert_close(stronger.after(weaker, 0.5).rating, 1600.0 + K * (0.5 - 10.0 / 11.0));
+        assert_close(weaker.after(stronger, 0.5).rating, 1200.0 + K * (0.5 - 1.0 / 11.0));
+        // and a draw between equals changes nothing
+        assert_close(rating(1400.0, 0).after(rating(1400.0, 0), 0.5).rating, 1400.0);
+    }
+}
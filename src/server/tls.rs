@@ -0,0 +1,117 @@
+//! TLS for incoming connections. The certificate is reloaded when its
+//! files change or the server receives `SIGHUP`, so renewing it doesn't
+//! need a restart. Connections that are already open are unaffected.
+
+use std::{
+    fs,
+    net::TcpStream,
+    path::{Path, PathBuf},
+    sync::{atomic::{AtomicBool, Ordering}, Arc, PoisonError, RwLock},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+use native_tls::{HandshakeError, Identity, TlsAcceptor, TlsStream};
+
+/// How often the certificate files are checked for changes.
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Where the server's certificate and private key are read from.
+pub enum Keys {
+    /// A PKCS #12 bundle, e.g. exported with `openssl pkcs12 -export`.
+    Keystore { path: PathBuf, password: String },
+    /// A PEM certificate chain and PKCS #8 private key,
+    /// e.g. `fullchain.pem` and `privkey.pem` from certbot.
+    Pem { cert: PathBuf, key: PathBuf },
+}
+
+impl Keys {
+    fn files(&self) -> Vec<&Path> {
+        match self {
+            Keys::Keystore { path, .. } => vec![path],
+            Keys::Pem { cert, key } => vec![cert, key],
+        }
+    }
+
+    fn load(&self) -> Result<TlsAcceptor, String> {
+        let read = |path: &Path| fs::read(path).map_err(|e| format!("Unable to read {}. {e}", path.display()));
+        let identity = match self {
+            Keys::Keystore { path, password } => Identity::from_pkcs12(&read(path)?, password)
+                .map_err(|e| format!("Invalid keystore {}. {e}", path.display()))?,
+            Keys::Pem { cert, key } => Identity::from_pkcs8(&read(cert)?, &read(key)?)
+                .map_err(|e| format!("Invalid certificate {} or key {}. {e}", cert.display(), key.display()))?,
+        };
+        TlsAcceptor::new(identity).map_err(|e| e.to_string())
+    }
+
+    /// Last modified times of the files, following symlinks
+    /// such as the ones certbot keeps in `live/`.
+    fn modified(&self) -> Vec<Option<SystemTime>> {
+        self.files()
+            .into_iter()
+            .map(|path| fs::metadata(path).and_then(|m| m.modified()).ok())
+            .collect()
+    }
+}
+
+/// Accepts TLS connections with the latest certificate.
+#[derive(Clone)]
+pub struct Tls {
+    keys: Arc<Keys>,
+    acceptor: Arc<RwLock<Arc<TlsAcceptor>>>,
+}
+
+impl Tls {
+    pub fn new(keys: Keys) -> Result<Self, String> {
+        let acceptor = keys.load()?;
+        Ok(Tls {
+            keys: Arc::new(keys),
+            acceptor: Arc::new(RwLock::new(Arc::new(acceptor))),
+        })
+    }
+
+    pub fn accept(&self, stream: TcpStream) -> Result<TlsStream<TcpStream>, HandshakeError<TcpStream>> {
+        // clone it out so a reload doesn't wait on a slow handshake
+        let acceptor = self.acceptor.read().unwrap_or_else(PoisonError::into_inner).clone();
+        acceptor.accept(stream)
+    }
+
+    /// Loads the certificate again, keeping the old one if it fails.
+    pub fn reload(&self) {
+        match self.keys.load() {
+            Ok(acceptor) => {
+                *self.acceptor.write().unwrap_or_else(PoisonError::into_inner) = Arc::new(acceptor);
+                log::info!("Reloaded the tls certificate");
+            },
+            Err(e) => log::error!("Keeping the current tls certificate. {e}"),
+        }
+    }
+
+    /// Spawns a thread to reload the certificate when its files
+    /// change or when the server gets `SIGHUP`.
+    pub fn watch(&self) {
+        let hangup = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        if let Err(e) = signal_hook::flag::register(signal_hook::consts::SIGHUP, hangup.clone()) {
+            log::warn!("Unable to reload the certificate on SIGHUP. {e}");
+        }
+
+        let tls = self.clone();
+        thread::spawn(move|| {
+            let mut loaded = tls.keys.modified();
+            let mut last = loaded.clone();
+            loop {
+                thread::sleep(WATCH_INTERVAL);
+                let modified = tls.keys.modified();
+                // wait for the files to stop changing so a certificate
+                // isn't loaded before its new key has been written
+                let settled = modified == last && modified != loaded;
+                if hangup.swap(false, Ordering::Relaxed) || settled {
+                    tls.reload();
+                    loaded = modified.clone();
+                }
+                last = modified;
+            }
+        });
+    }
+}
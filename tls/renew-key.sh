@@ -1,5 +1,8 @@
-## renew key and convert to pkcs
+## renew key and convert to pkcs for the default keystore. the server picks
+## up the new certificate without a restart either way, or skip the export
+## when it is run with --cert /etc/letsencrypt/live/ws.gh.maygoo.au/fullchain.pem
+##                       --key /etc/letsencrypt/live/ws.gh.maygoo.au/privkey.pem
 
 sudo certbot renew
 
-sudo openssl pkcs12 -export -in /etc/letsencrypt/live/ws.gh.maygoo.au/fullchain.pem -inkey /etc/letsencrypt/live/ws.gh.maygoo.au/privkey.pem -out ~/board-games-rust/tls/keystore.pkcs
\ No newline at end of file
+sudo openssl pkcs12 -export -in /etc/letsencrypt/live/ws.gh.maygoo.au/fullchain.pem -inkey /etc/letsencrypt/live/ws.gh.maygoo.au/privkey.pem -out ~/board-games-rust/tls/keystore.pkcs
@@ -1,9 +1,11 @@
-var cacheName = 'egui-template-pwa';
+var cacheName = 'board-games-pwa';
+/* Names of the files built by trunk, see `filehash` in Trunk.toml */
 var filesToCache = [
   './',
   './index.html',
-  './eframe_template.js',
-  './eframe_template_bg.wasm',
+  './web.js',
+  './web_bg.wasm',
+  './favicon.ico',
 ];
 
 /* Start the service worker and cache all of the app's content */
@@ -15,11 +17,19 @@ self.addEventListener('install', function (e) {
   );
 });
 
-/* Serve cached content when offline */
+/* Serve the latest files when online, keeping the cache up to date,
+   and fall back to the cached content when offline */
 self.addEventListener('fetch', function (e) {
+  if (e.request.method !== 'GET') return;
   e.respondWith(
-    caches.match(e.request).then(function (response) {
-      return response || fetch(e.request);
+    fetch(e.request).then(function (response) {
+      var copy = response.clone();
+      caches.open(cacheName).then(function (cache) {
+        cache.put(e.request, copy);
+      });
+      return response;
+    }).catch(function () {
+      return caches.match(e.request);
     })
   );
 });
@@ -1,11 +1,18 @@
 use gloo_net::websocket::futures::WebSocket;
 
+use common::{AccountRequest, ChatChannel, ChatMessage, ClientMessage, Credentials, Game, LiveGame, Ranking, ServerMessage};
+use common::ai::Difficulty;
+use common::history::{self, GameRecord, GameSummary};
 use common::tic_tac_toe::{
     ClientState,
     Piece,
     Message,
     Board,
     Turn,
+    End,
+    Offer,
+    Clocks,
+    SpectatorState,
     //ServerState
 };
 use crate::log;
@@ -16,6 +23,12 @@ use style::Style;
 mod worker;
 use worker::Worker;
 
+mod replay;
+use replay::Replay;
+
+mod offline;
+use offline::{Offline, Opponent};
+
 struct Info {
     pub text: String,
     locked: bool,
@@ -47,23 +60,122 @@ impl Info {
     }
 }
 
+/// Chat messages kept before the oldest are dropped.
+const CHAT_HISTORY: usize = 100;
+
+/// What is shown once connected to the server.
+enum View {
+    Game,
+    Live,
+    History,
+    Replay(Box<Replay>),
+}
+
+/// A live game being watched, kept after
+/// it ends so the final board can be seen.
+struct Spectating {
+    state: SpectatorState,
+    end: Option<End>,
+    countdown: Option<Countdown>,
+}
+
+/// Clocks sent at the start of a turn, counted
+/// down locally until the next turn starts.
+struct Countdown {
+    clocks: Clocks,
+    /// Time the clocks were first shown.
+    since: Option<f64>,
+}
+
+impl Countdown {
+    fn new(clocks: Clocks) -> Self {
+        Countdown {
+            clocks,
+            since: None,
+        }
+    }
+
+    /// Shows both clocks, only counting down for the player to move.
+    fn show(&mut self, ui: &mut egui::Ui, moving: &Piece) {
+        let now = ui.input().time;
+        let elapsed = std::time::Duration::from_secs_f64(now - *self.since.get_or_insert(now));
+        let left = |piece: Piece| {
+            let remaining = self.clocks.get(&piece);
+            let remaining = if piece == *moving { remaining.saturating_sub(elapsed) } else { remaining };
+            format!("{piece} {}", common::format_clock(remaining))
+        };
+        ui.label(format!("{}    {}", left(Piece::Cross), left(Piece::Nought)));
+        ui.ctx().request_repaint_after(std::time::Duration::from_millis(200));
+    }
+}
+
 pub struct WebApp {
     // Example stuff:
     remote_ip: String,
+    /// Connect with `wss://`, or plain `ws://` for local servers.
+    secure: bool,
     state: ClientState,
     worker: Option<Worker>,
     info: Info,
     n_players: usize,
+    username: String,
+    password: String,
+    /// Username once logged in, otherwise playing as a guest.
+    account: Option<String>,
+    leaderboard: Vec<Ranking>,
+    /// Whether we have asked the server to find a game.
+    queued: bool,
+    view: View,
+    history: Vec<GameSummary>,
+    /// Text of a game record to open in the replay viewer.
+    import: String,
+    import_error: String,
+    live_games: Vec<LiveGame>,
+    spectating: Option<Spectating>,
+    /// Number of spectators watching our own game.
+    spectators: usize,
+    countdown: Option<Countdown>,
+    chat: Vec<ChatMessage>,
+    chat_input: String,
+    /// Channel shown in the chat panel and sent to.
+    chat_channel: ChatChannel,
+    /// Game played without connecting to the server.
+    offline: Option<Offline>,
+    /// Whether a game restored after a restart is waiting for the opponent.
+    resuming: bool,
+    /// Whether we are seated in a game, from its preamble
+    /// until both players go back to the lobby.
+    playing: bool,
 }
 
 impl Default for WebApp {
     fn default() -> Self {
         Self {
             remote_ip: common::REMOTE_IP.to_owned(),
+            secure: true,
             state: ClientState::new(String::new(), Piece::Empty, 0),
             worker: None,
             info: Info::new(),
             n_players: 0,
+            username: String::new(),
+            password: String::new(),
+            account: None,
+            leaderboard: Vec::new(),
+            queued: false,
+            view: View::Game,
+            history: Vec::new(),
+            import: String::new(),
+            import_error: String::new(),
+            live_games: Vec::new(),
+            spectating: None,
+            spectators: 0,
+            countdown: None,
+            chat: Vec::new(),
+            chat_input: String::new(),
+            chat_channel: ChatChannel::Lobby,
+            offline: None,
+            resuming: false,
+            playing: false,
         }
     }
 }
@@ -76,6 +188,225 @@ impl WebApp {
 
         Default::default()
     }
+
+    /// Opens the websocket, optionally sending an
+    /// account request as the first message.
+    fn connect(&mut self, login: Option<ClientMessage>) {
+        let scheme = if self.secure { "wss" } else { "ws" };
+        let ip = format!("{scheme}://{}:{}", &self.remote_ip, common::REMOTE_PORT);
+        match WebSocket::open(&ip) {
+            Ok(ws) => {
+                let worker = Worker::new(ws);
+                self.queued = login.is_none();
+                match login {
+                    // wait until logged in before looking for a game
+                    Some(login) => worker.tx.send(common::Message::from(login).into()).unwrap(),
+                    None => worker.tx.send(common::Message::from(ClientMessage::FindGame(Game::TicTacToe)).into()).unwrap(),
+                }
+                self.worker = Some(worker);
+                self.request_leaderboard();
+            },
+            Err(e) => log!("can't connect to websocket: {e}"),
+        }
+    }
+
+    fn request_leaderboard(&self) {
+        if let Some(worker) = &self.worker {
+            worker.tx.send(common::Message::from(ClientMessage::Leaderboard(Game::TicTacToe)).into()).unwrap();
+        }
+    }
+
+    fn request_history(&self) {
+        if let Some(worker) = &self.worker {
+            worker.tx.send(common::Message::from(ClientMessage::History(self.account.clone())).into()).unwrap();
+        }
+    }
+
+    fn send(&self, msg: impl Into<common::Message>) {
+        if let Some(worker) = &self.worker {
+            worker.tx.send(msg.into().into()).unwrap();
+        }
+    }
+
+    fn credentials(&self) -> Credentials {
+        Credentials {
+            username: self.username.clone(),
+            password: self.password.clone(),
+        }
+    }
+
+    fn server_message(&mut self, msg: ServerMessage) {
+        let login_handled = matches!(msg, ServerMessage::LoggedIn { .. } | ServerMessage::AccountError(_));
+        if login_handled && !self.queued {
+            if let Some(worker) = &self.worker {
+                worker.tx.send(common::Message::from(ClientMessage::FindGame(Game::TicTacToe)).into()).unwrap();
+                self.queued = true;
+            }
+        }
+
+        match msg {
+            ServerMessage::Ping(status) => self.n_players = status.n_players,
+            ServerMessage::LoggedIn { username, .. } => {
+                self.password.clear();
+                self.account = Some(username);
+            },
+            ServerMessage::LoggedOut => self.account = None,
+            ServerMessage::AccountError(e) | ServerMessage::Error(e) => {
+                self.info.unlock().update(e).lock();
+            },
+            ServerMessage::Leaderboard(_, rankings) => self.leaderboard = rankings,
+            ServerMessage::History(games) => self.history = games,
+            ServerMessage::GameRecord(record) => self.view = View::Replay(Box::new(Replay::new(record))),
+            ServerMessage::LiveGames(games) => self.live_games = games,
+            ServerMessage::Chat(msg) => {
+                self.chat.push(msg);
+                if self.chat.len() > CHAT_HISTORY {
+                    self.chat.remove(0);
+                }
+            },
+            ServerMessage::RatingChange { rating, change, .. } => {
+                let text = format!("{}\nYour rating is now {rating} ({change:+})", self.info.text);
+                self.info.unlock().update(text).lock();
+            },
+            ServerMessage::StoppedSpectating => {
+                self.spectating = None;
+                self.send(ClientMessage::LiveGames);
+            },
+            ServerMessage::Suspended(game) => {
+                let text = format!("Your game of {} has been saved, it carries on when you both log back in after the restart", game.name());
+                self.info.unlock().update(text).lock();
+            },
+            ServerMessage::Resuming(game, opponent) => {
+                self.resuming = true;
+                let text = format!("Your game of {} will carry on once {opponent} is back", game.name());
+                self.info.unlock().update(text).lock();
+            },
+            ServerMessage::Shutdown(remaining) => {
+                let text = format!("The server is shutting down, games in progress have {} left to finish", common::format_clock(remaining));
+                self.info.unlock().update(text).lock();
+            },
+        }
+    }
+
+    fn game_message(&mut self, msg: Message) {
+        // messages for a live game are only sent until it ends
+        if let Some(spectating) = self.spectating.as_mut().filter(|s| s.end.is_none()) {
+            match msg {
+                Message::Move((p, x, y)) => {
+                    spectating.state.board.place(p.clone(), x, y);
+                    spectating.state.current_player = p.next();
+                    return;
+                },
+                Message::Spectators(n) => {
+                    spectating.state.spectators = n;
+                    return;
+                },
+                Message::WaitTurn(clocks) => {
+                    spectating.countdown = clocks.map(Countdown::new);
+                    return;
+                },
+                Message::TakeBack(moves) => {
+                    for (p, x, y) in moves {
+                        spectating.state.board.place(Piece::Empty, x, y);
+                        spectating.state.current_player = p;
+                    }
+                    return;
+                },
+                Message::GameOver(end) => {
+                    spectating.end = Some(end);
+                    return;
+                },
+                _ => (),
+            }
+        }
+
+        // moves from a game we have stopped watching may still
+        // arrive, only our own game's messages are left
+        let ours = self.playing || self.resuming || matches!(msg, Message::Preamble(_) | Message::Spectate(_));
+        if !ours {
+            return;
+        }
+
+        match msg {
+            Message::Preamble(config) => {
+                self.playing = true;
+                self.state = config;
+                self.state.board = Board::new(self.state.board.size);
+                self.spectators = 0;
+                self.countdown = None;
+                self.spectating = None;
+                self.resuming = false;
+                self.info.unlock();
+                // always show a new game
+                self.view = View::Game;
+            },
+            Message::WaitTurn(clocks) => {
+                self.state.turn = Turn::TurnWait;
+                self.countdown = clocks.map(Countdown::new);
+            },
+            Message::YourTurn(clocks) => {
+                self.state.turn =Turn::TurnStart;
+                self.countdown = clocks.map(Countdown::new);
+            },
+            Message::Move((p, x, y)) => {
+                self.state.board.place(p, x, y);
+                self.state.offer = None;
+                self.info.unlock();
+            },
+            Message::Offer(offer) => self.state.offer = Some(offer),
+            Message::Answer(offer, _) => {
+                let text = match offer {
+                    Offer::Draw => "Your opponent declined the draw",
+                    Offer::Takeback => "Your opponent declined the takeback",
+                    Offer::Rematch => {
+                        // back in the lobby waiting for a new opponent
+                        self.playing = false;
+                        self.state.turn = Turn::Begin;
+                        self.state.offer = None;
+                        self.state.board = Board::new(self.state.board.size);
+                        self.countdown = None;
+                        self.info.unlock();
+                        return;
+                    },
+                };
+                self.info.unlock().update(text.to_string()).lock();
+            },
+            Message::TakeBack(moves) => {
+                for (_, x, y) in moves {
+                    self.state.board.place(Piece::Empty, x, y);
+                }
+                self.state.offer = None;
+                self.info.unlock();
+            },
+            Message::Resign => (), // only sent by clients
+            Message::Spectate(state) => {
+                let countdown = state.clocks.map(Countdown::new);
+                self.spectating = Some(Spectating { state, end: None, countdown });
+                self.view = View::Live;
+            },
+            Message::Spectators(n) => self.spectators = n,
+            Message::InvalidMove(err) => {
+                self.info.unlock().update(err).lock();
+                self.state.turn = Turn::TurnStart;
+            },
+            Message::GameOver(end) => {
+                self.info.unlock().update(end_text(&end, &self.state.piece).to_string()).lock();
+                self.state.offer = None;
+                // a restored game was given up
+                self.resuming = false;
+
+                // no rematch once the opponent has left
+                self.state.turn = match end {
+                    End::Disconnect | End::Aborted => Turn::Begin,
+                    _ => Turn::End,
+                };
+                self.playing = self.state.turn == Turn::End;
+
+                // ratings may have changed
+                self.request_leaderboard();
+            }
+        }
+    }
 }
 
 impl eframe::App for WebApp {
@@ -91,9 +422,16 @@ impl eframe::App for WebApp {
 
             ui.columns(3, |columns| {
                 columns[0].horizontal_centered(|ui| {
+                    if self.offline.is_some() && ui.button("⬅").clicked() {
+                        self.offline = None;
+                    }
                     if self.worker.is_some() && ui.button("⬅").clicked() {
                         self.worker.as_ref().unwrap().tx.send(vec![0u8]).unwrap();
                         self.worker = None;
+                        self.account = None;
+                        self.spectating = None;
+                        self.chat.clear();
+                        self.view = View::Game;
                     }
                     ui.heading("Board Games");
                 });
@@ -104,7 +442,7 @@ impl eframe::App for WebApp {
                     });
                     columns[2].with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
                         // display 'disconnected'/error messages at the top right ?
-                        ui.label("top right");
+                        ui.label(self.account.as_deref().unwrap_or("Guest"));
                     });
                 }
             });
@@ -122,104 +460,411 @@ impl eframe::App for WebApp {
             });
         });
 
+        if self.worker.is_some() {
+            egui::SidePanel::right("chat").show(ctx, |ui| self.show_chat(ui));
+        }
+
         egui::CentralPanel::default().show(ctx, |ui| {
 
             ui.vertical_centered(|ui| {
-                if self.worker.is_none() {
+                if let Some(offline) = &mut self.offline {
+                    offline.show(ui);
+                } else if self.worker.is_none() {
 
                     ui.add(egui::widgets::TextEdit::singleline(&mut self.remote_ip)
                         .text_color(Style::CORAL));
+                    ui.checkbox(&mut self.secure, "Secure connection (wss)");
 
                     if ui.button("Connect to the server").clicked() && self.worker.is_none() {
-                        let ip = format!("wss://{}:{}", &self.remote_ip, common::REMOTE_PORT);
-                        match WebSocket::open(&ip) {
-                            Ok(ws) => {
-                                self.worker = Some(Worker::new(ws));
-                            },
-                            Err(e) => log!("can't connect to websocket: {e}"),
+                        self.connect(None);
+                    }
+
+                    ui.separator();
+                    ui.label("Or log in to keep your name between games");
+                    ui.add(egui::widgets::TextEdit::singleline(&mut self.username)
+                        .hint_text("Username"));
+                    ui.add(egui::widgets::TextEdit::singleline(&mut self.password)
+                        .hint_text("Password")
+                        .password(true));
+
+                    if ui.button("Log in").clicked() && self.worker.is_none() {
+                        self.connect(Some(ClientMessage::Account(AccountRequest::Login(self.credentials()))));
+                    }
+                    if ui.button("Sign up").clicked() && self.worker.is_none() {
+                        self.connect(Some(ClientMessage::Account(AccountRequest::SignUp(self.credentials()))));
+                    }
+
+                    ui.separator();
+                    ui.label("Or play offline against the computer");
+                    ui.horizontal(|ui| {
+                        for difficulty in Difficulty::ALL {
+                            if ui.button(difficulty.to_string()).clicked() {
+                                self.offline = Some(Offline::against_ai(difficulty, ui.input().time));
+                            }
                         }
+                    });
+                    if ui.button("Two players on this device").clicked() {
+                        self.offline = Some(Offline::new(Opponent::HotSeat));
                     }
 
                 } else {
-                    #[cfg(debug_assertions)]
-                    ui.label(format!{"State: {:?}", self.state});
-
-                    ui.label(format!("You are player: {}", self.state.piece));
-                    ui.label(&self.info.text);
-
-                    match self.state.turn {
-                        Turn::Begin => self.info.update("Wait for another player to appear".to_string()),
-                        Turn::TurnStart => self.info.update("It is your turn!".to_string()),
-                        Turn::TurnWait => self.info.update("Wait for your opponent to make their turn".to_string()),
-                        Turn::End => {
-                            // prompt player to play again
-                            &mut self.info
-                        },
-                    };
-
                     // consume messages from the channel
                     if let Ok(msg) = self.worker.as_ref().unwrap().rx.try_recv() {
-                        match msg.into() {
-                            Message::Preamble(config) => {
-                                self.state = config;
-                                self.state.board = Board::new(self.state.board.size);
-                            },
-                            Message::WaitTurn => self.state.turn = Turn::TurnWait,
-                            Message::YourTurn => self.state.turn =Turn::TurnStart,
-                            Message::Move((p, x, y)) => {
-                                self.state.board.place(p, x, y);
-                                self.info.unlock();
-                            },
-                            Message::InvalidMove(err) => {
-                                self.info.unlock().update(err).lock();
-                                self.state.turn = Turn::TurnStart;
+                        match common::Message::try_from(msg) {
+                            Ok(common::Message::Server(msg)) => self.server_message(msg),
+                            Ok(common::Message::Client(_)) => (), // only sent by clients
+                            Ok(common::Message::TicTacToe(msg)) => self.game_message(msg),
+                            Err(e) => {
+                                self.info.unlock().update(format!("Invalid message from the server. {e}")).lock();
                             },
-                            Message::GameOver(end) => {
-                                self.info.unlock().update(format!("{end:?}")).lock();
+                        }
+                    }
 
-                                self.state.turn = Turn::End;
-                                // display window popup
-                            }
-                            Message::Status(status) => {
-                                self.n_players = status.n_players;
-                            }
+                    ui.horizontal(|ui| {
+                        if ui.selectable_label(matches!(self.view, View::Game), "Game").clicked() {
+                            self.view = View::Game;
+                        }
+                        if ui.selectable_label(matches!(self.view, View::Live), "Live games").clicked() {
+                            self.view = View::Live;
+                            self.send(ClientMessage::LiveGames);
                         }
+                        if ui.selectable_label(matches!(self.view, View::History | View::Replay(_)), "Past games").clicked() {
+                            self.view = View::History;
+                            self.request_history();
+                        }
+                    });
+                    ui.separator();
+
+                    match self.view {
+                        View::Game => self.show_game(ui),
+                        View::Live => self.show_live(ui),
+                        View::History => self.show_history(ui),
+                        View::Replay(ref mut replay) => replay.show(ui),
+                    }
+                }
+            });
+        });
+    }
+}
+
+impl WebApp {
+    fn show_game(&mut self, ui: &mut egui::Ui) {
+        #[cfg(debug_assertions)]
+        ui.label(format!{"State: {:?}", self.state});
+
+        ui.label(format!("You are player: {}", with_rating(&self.state.piece.to_string(), self.state.rating)));
+        if self.state.turn != Turn::Begin {
+            ui.label(format!("Playing against: {}", with_rating(&self.state.opponent, self.state.opponent_rating)));
+        }
+        if let Some(time_control) = self.state.time_control {
+            ui.label(format!("Time control: {time_control}"));
+        }
+        ui.label(&self.info.text);
+        if let Some(countdown) = &mut self.countdown {
+            match self.state.turn {
+                Turn::TurnStart => countdown.show(ui, &self.state.piece),
+                Turn::TurnWait => countdown.show(ui, &self.state.piece.clone().next()),
+                _ => (),
+            }
+        }
+        if self.spectators > 0 {
+            ui.label(format!("Spectators: {}", self.spectators));
+        }
+
+        if matches!(self.state.turn, Turn::TurnStart | Turn::TurnWait) {
+            self.show_offers(ui);
+        } else if self.state.turn == Turn::End {
+            self.show_rematch(ui);
+        }
+
+        if self.resuming && self.state.turn == Turn::Begin && ui.button("Abandon game").clicked() {
+            self.resuming = false;
+            self.send(ClientMessage::AbandonGame);
+        }
+
+        if self.state.turn == Turn::Begin && self.worker.is_some() {
+            ui.horizontal(|ui| {
+                ui.label("Or play the computer:");
+                for difficulty in Difficulty::ALL {
+                    if ui.button(difficulty.to_string()).clicked() {
+                        self.send(ClientMessage::PlayBot(Game::TicTacToe, difficulty));
                     }
+                }
+            });
+        }
 
-                    // move this into a widget ? but then how to pull out the individual click responses ?
-                    egui_extras::StripBuilder::new(ui)
-                        .size(egui_extras::Size::remainder()) // left padding
-                        .sizes(egui_extras::Size::relative(0.3), 1) // board spacing
-                        .size(egui_extras::Size::remainder()) // right padding
-                        .horizontal(|mut strip| {
-                            // left padding
-                            strip.empty();
-
-                            strip.cell(|ui| {
-                                let size = ui.available_size();
-                                if let Some((x, y)) = display_board(ui, &self.state.board, self.state.turn == Turn::TurnStart, size) {
-                                    self.state.turn = Turn::TurnWait;
-                    
-                                    self.worker
-                                        .as_ref()
-                                        .unwrap()
-                                        .tx.send(
-                                            Message::Move(
-                                                (self.state.piece.clone(),
-                                                x,
-                                                y
-                                            )).into()
-                                        ).unwrap();
-                                }
-                            });
-
-                            // right padding
-                            strip.empty();
-                        });
+        if self.state.turn == Turn::Begin && !self.leaderboard.is_empty() {
+            ui.separator();
+            ui.heading("Leaderboard");
+            egui::Grid::new("leaderboard").striped(true).show(ui, |ui| {
+                for (i, ranking) in self.leaderboard.iter().enumerate() {
+                    ui.label(format!("{}.", i + 1));
+                    ui.label(&ranking.username);
+                    ui.label(ranking.rating.to_string());
+                    ui.end_row();
                 }
             });
+        }
+
+        match self.state.turn {
+            Turn::Begin => self.info.update("Wait for another player to appear".to_string()),
+            Turn::TurnStart => self.info.update("It is your turn!".to_string()),
+            Turn::TurnWait => self.info.update("Wait for your opponent to make their turn".to_string()),
+            Turn::End => {
+                // prompt player to play again
+                &mut self.info
+            },
+        };
+
+        if let Some((x, y)) = centered_board(ui, &self.state.board, self.state.turn == Turn::TurnStart) {
+            self.state.turn = Turn::TurnWait;
+
+            self.worker
+                .as_ref()
+                .unwrap()
+                .tx.send(
+                    common::Message::from(Message::Move(
+                        (self.state.piece.clone(),
+                        x,
+                        y
+                    ))).into()
+                ).unwrap();
+        }
+    }
+
+    /// Buttons to resign or make an offer, and to answer the opponent's offer.
+    fn show_offers(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            if ui.button("Resign").clicked() {
+                self.send(Message::Resign);
+            }
+            if ui.button("Offer draw").clicked() {
+                self.send(Message::Offer(Offer::Draw));
+                self.info.unlock().update("Draw offered".to_string()).lock();
+            }
+            if ui.button("Take back").clicked() {
+                self.send(Message::Offer(Offer::Takeback));
+                self.info.unlock().update("Asked to take back your last move".to_string()).lock();
+            }
+        });
+        self.show_answer(ui);
+    }
+
+    /// Buttons to accept or decline the opponent's offer, if there is one.
+    fn show_answer(&mut self, ui: &mut egui::Ui) {
+        if let Some(offer) = self.state.offer {
+            ui.horizontal(|ui| {
+                match offer {
+                    Offer::Draw => ui.label("Your opponent offers a draw"),
+                    Offer::Takeback => ui.label("Your opponent asks to take back their last move"),
+                    Offer::Rematch => ui.label("Your opponent wants a rematch"),
+                };
+                if ui.button("Accept").clicked() {
+                    self.send(Message::Answer(offer, true));
+                    self.state.offer = None;
+                }
+                if ui.button("Decline").clicked() {
+                    self.send(Message::Answer(offer, false));
+                    self.state.offer = None;
+                }
+            });
+        }
+    }
+
+    /// Buttons to play the same opponent again or go back to the lobby.
+    fn show_rematch(&mut self, ui: &mut egui::Ui) {
+        if self.state.offer.is_some() {
+            // answered like any other offer
+            return self.show_answer(ui);
+        }
+        ui.horizontal(|ui| {
+            if ui.button("Play again").clicked() {
+                self.send(Message::Offer(Offer::Rematch));
+                self.info.unlock().update("Rematch offered".to_string()).lock();
+            }
+            if ui.button("Back to lobby").clicked() {
+                self.send(Message::Answer(Offer::Rematch, false));
+            }
         });
     }
+
+    /// Lists the games being played, or the
+    /// board of the one being spectated.
+    fn show_live(&mut self, ui: &mut egui::Ui) {
+        let Some(spectating) = &mut self.spectating else {
+            if ui.button("Refresh").clicked() {
+                self.send(ClientMessage::LiveGames);
+            }
+            if self.live_games.is_empty() {
+                ui.label("No games are being played right now");
+            }
+
+            egui::Grid::new("live").striped(true).show(ui, |ui| {
+                for game in self.live_games.iter() {
+                    ui.label(format!("{} vs {}", game.players[0], game.players[1]));
+                    ui.label(format!("{} watching", game.spectators));
+                    if ui.button("Watch").clicked() {
+                        self.send(ClientMessage::Spectate(game.id));
+                    }
+                    ui.end_row();
+                }
+            });
+            return;
+        };
+
+        let state = &spectating.state;
+        ui.heading(format!("{} vs {}",
+            with_rating(&state.players[0], state.ratings[0]),
+            with_rating(&state.players[1], state.ratings[1]),
+        ));
+        match &spectating.end {
+            None => ui.label(format!("{} to play", state.current_player)),
+            Some(End::Victory(Piece::Cross)) => ui.label(format!("{} won", state.players[0])),
+            Some(End::Victory(_)) => ui.label(format!("{} won", state.players[1])),
+            Some(End::Draw) => ui.label("Draw"),
+            Some(End::Disconnect) => ui.label("A player disconnected"),
+            Some(End::Timeout(Piece::Cross)) => ui.label(format!("{} won on time", state.players[1])),
+            Some(End::Timeout(_)) => ui.label(format!("{} won on time", state.players[0])),
+            Some(End::Resignation(Piece::Cross)) => ui.label(format!("{} won by resignation", state.players[1])),
+            Some(End::Resignation(_)) => ui.label(format!("{} won by resignation", state.players[0])),
+            Some(End::AgreedDraw) => ui.label("Draw by agreement"),
+            Some(End::Aborted) => ui.label("Stopped by the server"),
+        };
+        if let (Some(countdown), None) = (&mut spectating.countdown, &spectating.end) {
+            countdown.show(ui, &state.current_player);
+        }
+        ui.label(format!("Spectators: {}", state.spectators));
+
+        if ui.button("Stop watching").clicked() {
+            if spectating.end.is_none() {
+                // left until the server confirms, moves may still be on the way
+                self.send(ClientMessage::StopSpectating);
+            } else {
+                self.spectating = None;
+                self.send(ClientMessage::LiveGames);
+            }
+            return;
+        }
+
+        centered_board(ui, &state.board, false);
+    }
+
+    fn show_chat(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.chat_channel, ChatChannel::Lobby, "Lobby");
+            ui.selectable_value(&mut self.chat_channel, ChatChannel::Game, "Game");
+        });
+        ui.separator();
+
+        let input = ui.add(egui::TextEdit::singleline(&mut self.chat_input)
+            .hint_text("Say something"));
+        // the server rejects longer messages
+        if let Some((i, _)) = self.chat_input.char_indices().nth(common::MAX_CHAT_LEN) {
+            self.chat_input.truncate(i);
+        }
+        let enter = input.lost_focus() && ui.input().key_pressed(egui::Key::Enter);
+        if (ui.button("Send").clicked() || enter) && !self.chat_input.trim().is_empty() {
+            let text = std::mem::take(&mut self.chat_input);
+            self.send(ClientMessage::Chat(self.chat_channel, text));
+            input.request_focus();
+        }
+        ui.separator();
+
+        egui::ScrollArea::vertical().stick_to_bottom(true).show(ui, |ui| {
+            for msg in self.chat.iter().filter(|msg| msg.channel == self.chat_channel) {
+                ui.label(format!("{}: {}", msg.from, msg.text));
+            }
+        });
+    }
+
+    /// Lists past games, your own if logged in,
+    /// or opens a game record pasted in by the player.
+    fn show_history(&mut self, ui: &mut egui::Ui) {
+        ui.collapsing("Import", |ui| {
+            ui.add(egui::TextEdit::multiline(&mut self.import)
+                .hint_text("Paste an exported game here")
+                .code_editor());
+            if ui.button("Open").clicked() {
+                match GameRecord::import(&self.import) {
+                    Ok(record) => {
+                        self.import_error.clear();
+                        self.view = View::Replay(Box::new(Replay::new(record)));
+                    },
+                    Err(e) => self.import_error = e,
+                }
+            }
+            ui.label(&self.import_error);
+        });
+
+        if self.history.is_empty() {
+            ui.label("No finished games yet");
+        }
+
+        egui::Grid::new("history").striped(true).show(ui, |ui| {
+            for game in self.history.iter() {
+                ui.label(history::format_time(game.started));
+                ui.label(format!("{} vs {}", game.players[0], game.players[1]));
+                ui.label(game.result_text());
+                if ui.button("Watch").clicked() {
+                    self.worker
+                        .as_ref()
+                        .unwrap()
+                        .tx.send(common::Message::from(ClientMessage::GameRecord(game.id)).into())
+                        .unwrap();
+                }
+                ui.end_row();
+            }
+        });
+    }
+}
+
+/// Shows the board in the middle third of the available width,
+/// returning the cell that was clicked if `clickable`.
+fn centered_board(ui: &mut egui::Ui, board: &Board, clickable: bool) -> Option<(usize, usize)> {
+    let mut clicked = None;
+
+    // move this into a widget ? but then how to pull out the individual click responses ?
+    egui_extras::StripBuilder::new(ui)
+        .size(egui_extras::Size::remainder()) // left padding
+        .sizes(egui_extras::Size::relative(0.3), 1) // board spacing
+        .size(egui_extras::Size::remainder()) // right padding
+        .horizontal(|mut strip| {
+            // left padding
+            strip.empty();
+
+            strip.cell(|ui| {
+                let size = ui.available_size();
+                clicked = display_board(ui, board, clickable, size);
+            });
+
+            // right padding
+            strip.empty();
+        });
+
+    clicked
+}
+
+/// Result of a game from the point of view of the player with `piece`.
+fn end_text(end: &End, piece: &Piece) -> &'static str {
+    match end {
+        End::Victory(p) if p == piece => "You won!",
+        End::Victory(_) => "You lost",
+        End::Draw => "The game ended in a draw",
+        End::Disconnect => "Your opponent disconnected",
+        End::Timeout(p) if p == piece => "You ran out of time and lost",
+        End::Timeout(_) => "Your opponent ran out of time, you won!",
+        End::Resignation(p) if p == piece => "You resigned",
+        End::Resignation(_) => "Your opponent resigned, you won!",
+        End::AgreedDraw => "Draw by agreement",
+        End::Aborted => "The server stopped the game before it finished",
+    }
+}
+
+fn with_rating(name: &str, rating: Option<u32>) -> String {
+    match rating {
+        Some(rating) => format!("{name} ({rating})"),
+        None => name.to_string(),
+    }
 }
 
 fn display_board(ui: &mut egui::Ui, board: &Board, clickable: bool, size: egui::Vec2) -> Option<(usize, usize)> {
@@ -0,0 +1,104 @@
+use std::time::Duration;
+
+use common::ai::{Ai, Difficulty};
+use common::tic_tac_toe::{self, Board, End, Piece};
+
+/// Seconds the computer waits before moving.
+const AI_DELAY: f64 = 0.5;
+
+/// Who the player is up against without a server.
+pub enum Opponent {
+    Ai(Ai),
+    /// Both players take turns on this device.
+    HotSeat,
+}
+
+/// A game played entirely in the browser, using
+/// the same rules as the server from `common`.
+pub struct Offline {
+    board: Board,
+    current: Piece,
+    /// Piece played by the person at the keyboard against the
+    /// computer, colours swap every time they play again.
+    piece: Piece,
+    opponent: Opponent,
+    end: Option<End>,
+    /// Time the computer started thinking about its move.
+    thinking: Option<f64>,
+}
+
+impl Offline {
+    pub fn new(opponent: Opponent) -> Self {
+        Offline {
+            board: Board::new(tic_tac_toe::BOARD_SIZE),
+            current: Piece::Cross,
+            piece: Piece::Cross,
+            opponent,
+            end: None,
+            thinking: None,
+        }
+    }
+
+    /// Computer opponent seeded from the time the app has been open.
+    pub fn against_ai(difficulty: Difficulty, time: f64) -> Self {
+        Offline::new(Opponent::Ai(Ai::new(difficulty, (time * 1_000_000.0) as u64)))
+    }
+
+    fn ai_to_move(&self) -> bool {
+        matches!(self.opponent, Opponent::Ai(_)) && self.current != self.piece && self.end.is_none()
+    }
+
+    fn place(&mut self, x: usize, y: usize) {
+        if self.board.try_place(self.current.clone(), x, y).is_ok() {
+            self.end = self.board.check_victory(self.current.clone());
+            self.current = self.current.clone().next();
+        }
+    }
+
+    fn status(&self) -> String {
+        match (&self.end, &self.opponent) {
+            (Some(End::Victory(p)), Opponent::Ai(_)) if *p == self.piece => "You won!".to_string(),
+            (Some(End::Victory(_)), Opponent::Ai(_)) => "You lost".to_string(),
+            (Some(End::Victory(p)), Opponent::HotSeat) => format!("{p} won!"),
+            (Some(_), _) => "The game ended in a draw".to_string(),
+            (None, Opponent::Ai(_)) if self.ai_to_move() => "The computer is thinking".to_string(),
+            (None, Opponent::Ai(_)) => "It is your turn!".to_string(),
+            (None, Opponent::HotSeat) => format!("It is {}'s turn", self.current),
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        if self.ai_to_move() {
+            let now = ui.input().time;
+            let started = *self.thinking.get_or_insert(now);
+            if now - started >= AI_DELAY {
+                self.thinking = None;
+                let Opponent::Ai(ai) = &mut self.opponent else { unreachable!() };
+                if let Some((x, y)) = ai.choose_move(&self.board, &self.current) {
+                    self.place(x, y);
+                }
+            }
+            ui.ctx().request_repaint_after(Duration::from_secs_f64(AI_DELAY));
+        }
+
+        match &self.opponent {
+            Opponent::Ai(ai) => ui.label(format!("You are player {} against the computer ({})", self.piece, ai.difficulty)),
+            Opponent::HotSeat => ui.label("Two players on this device"),
+        };
+        ui.label(self.status());
+
+        if self.end.is_some() && ui.button("Play again").clicked() {
+            self.board = Board::new(self.board.size);
+            self.current = Piece::Cross;
+            self.end = None;
+            if let Opponent::Ai(_) = self.opponent {
+                self.piece = self.piece.clone().next();
+            }
+        }
+
+        let clickable = self.end.is_none() && !self.ai_to_move();
+        if let Some((x, y)) = super::centered_board(ui, &self.board, clickable) {
+            self.place(x, y);
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::time::Duration;
+
+use common::history::{self, GameRecord};
+use common::tic_tac_toe::{self, Board};
+
+/// Seconds between each move when autoplaying.
+const AUTOPLAY_DELAY: f64 = 1.0;
+
+/// Steps through the moves of a finished game.
+pub struct Replay {
+    record: GameRecord,
+    /// Number of moves currently shown on the board.
+    position: usize,
+    board: Board,
+    /// Time of the last autoplayed move, `None` when paused.
+    autoplay: Option<f64>,
+    /// The record in the PGN like text format.
+    export: String,
+}
+
+impl Replay {
+    pub fn new(record: GameRecord) -> Self {
+        let size = tic_tac_toe::parse_variant(&record.summary.variant).unwrap_or(tic_tac_toe::BOARD_SIZE);
+        Replay {
+            export: record.export(),
+            record,
+            position: 0,
+            board: Board::new(size),
+            autoplay: None,
+        }
+    }
+
+    /// Rebuilds the board with the first `position` moves.
+    fn seek(&mut self, position: usize) {
+        self.position = position.min(self.record.moves.len());
+        self.board = Board::new(self.board.size);
+        for (p, x, y) in self.record.moves[..self.position].iter().cloned() {
+            self.board.place(p, x, y);
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui) {
+        let n_moves = self.record.moves.len();
+
+        if let Some(last) = self.autoplay {
+            let now = ui.input().time;
+            if self.position >= n_moves {
+                self.autoplay = None;
+            } else if now - last >= AUTOPLAY_DELAY {
+                self.seek(self.position + 1);
+                self.autoplay = Some(now);
+            }
+            ui.ctx().request_repaint_after(Duration::from_secs_f64(AUTOPLAY_DELAY));
+        }
+
+        let summary = &self.record.summary;
+        ui.heading(format!("{} vs {}", summary.players[0], summary.players[1]));
+        ui.label(format!("{} {}, {}", summary.game.name(), summary.variant, history::format_time(summary.started)));
+
+        ui.horizontal(|ui| {
+            if ui.add_enabled(self.position > 0, egui::Button::new("⏮")).clicked() {
+                self.seek(0);
+            }
+            if ui.add_enabled(self.position > 0, egui::Button::new("⏴")).clicked() {
+                self.seek(self.position - 1);
+            }
+            let play = if self.autoplay.is_some() { "⏸" } else { "▶" };
+            if ui.add_enabled(self.position < n_moves, egui::Button::new(play)).clicked() {
+                self.autoplay = match self.autoplay {
+                    Some(_) => None,
+                    None => Some(ui.input().time),
+                };
+            }
+            if ui.add_enabled(self.position < n_moves, egui::Button::new("⏵")).clicked() {
+                self.seek(self.position + 1);
+            }
+            if ui.add_enabled(self.position < n_moves, egui::Button::new("⏭")).clicked() {
+                self.seek(n_moves);
+            }
+        });
+
+        match self.position.checked_sub(1).map(|i| &self.record.moves[i]) {
+            Some((p, x, y)) => ui.label(format!("Move {} of {n_moves}: {p} {} {}", self.position, (*y as u8 + b'A') as char, x + 1)),
+            None => ui.label(format!("Start, {n_moves} moves")),
+        };
+        if self.position == n_moves {
+            ui.label(self.record.summary.result_text());
+        }
+
+        super::centered_board(ui, &self.board, false);
+
+        ui.collapsing("Export", |ui| {
+            if ui.button("Copy to clipboard").clicked() {
+                ui.output().copied_text = self.export.clone();
+            }
+            ui.add(egui::TextEdit::multiline(&mut self.export.as_str()).code_editor());
+        });
+    }
+}